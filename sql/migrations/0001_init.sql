@@ -0,0 +1,151 @@
+-- Initial per-network schema, `{schema}` is replaced with the schema name of the network.
+--
+-- Every statement is idempotent, so that databases created by the former `sql/schema.sql`
+-- are adopted as version 1.
+
+CREATE TABLE IF NOT EXISTS {schema}.peer (
+    id                  SERIAL,
+    time                TIMESTAMPTZ     NOT NULL,
+    version             TEXT            NOT NULL,
+    ip                  TEXT            NOT NULL,
+    n_reachable         INT             NOT NULL DEFAULT 0,
+    address             TEXT            NULL,
+    peer_id             TEXT            NULL,
+    node_type           INT             NOT NULL DEFAULT 0
+);
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM pg_constraint
+        WHERE conname = 'unique_address' AND conrelid = '{schema}.peer'::regclass
+    ) THEN
+        ALTER TABLE {schema}.peer ADD CONSTRAINT unique_address UNIQUE (address);
+    END IF;
+END $$;
+
+CREATE TABLE IF NOT EXISTS {schema}.ipinfo (
+    ip                  TEXT             PRIMARY KEY NOT NULL,
+    country             TEXT             NULL,
+    city                TEXT             NULL,
+    loc                 TEXT             NULL,
+    region              TEXT             NULL,
+    company             TEXT             NULL,
+    latitude            NUMERIC(9, 6)    NULL,
+    longitude           NUMERIC(9, 6)    NULL
+);
+
+CREATE TABLE IF NOT EXISTS {schema}.block (
+    time                        TIMESTAMP       NOT NULL,
+    number                      BIGINT          NOT NULL,
+    n_transactions              INT             NOT NULL,
+    n_proposals                 INT             NOT NULL,
+    n_uncles                    INT             NOT NULL,
+    miner_lock_args             VARCHAR ( 100 ) NULL,
+    cellbase_client_version     VARCHAR ( 50 )  NULL,
+    cellbase_miner_source       VARCHAR ( 50 )  NULL,
+    interval                    BIGINT          NOT NULL,
+    hash                        VARCHAR ( 66 )  NULL,
+    PRIMARY KEY (number)
+);
+CREATE TABLE IF NOT EXISTS {schema}.tx_pool_info (
+    time                TIMESTAMP       NOT NULL,
+    total_tx_cycles     BIGINT          NOT NULL,
+    total_tx_size       BIGINT          NOT NULL,
+    pending             BIGINT          NOT NULL,
+    proposed            BIGINT          NOT NULL,
+    orphan              BIGINT          NOT NULL
+);
+CREATE TABLE IF NOT EXISTS {schema}.block_transaction (
+    time                TIMESTAMP       NOT NULL,
+    number              BIGINT          NOT NULL,
+    size                BIGINT          NOT NULL,
+    n_inputs            INT             NOT NULL,
+    n_outputs           INT             NOT NULL,
+    n_header_deps       INT             NOT NULL,
+    n_cell_deps         INT             NOT NULL,
+    total_data_size     BIGINT          NOT NULL,
+    proposal_id         VARCHAR ( 66 )  NOT NULL,
+    hash                VARCHAR ( 66 )  NOT NULL,
+    PRIMARY KEY (number)
+);
+CREATE TABLE IF NOT EXISTS {schema}.subscribed_new_transaction (
+    time                TIMESTAMP       NOT NULL,
+    size                BIGINT          NOT NULL,
+    cycles              BIGINT          NOT NULL,
+    fee                 BIGINT          NOT NULL,
+    n_inputs            INT             NOT NULL,
+    n_outputs           INT             NOT NULL,
+    n_header_deps       INT             NOT NULL,
+    n_cell_deps         INT             NOT NULL,
+    proposal_id         VARCHAR ( 66 )  NOT NULL,
+    hash                VARCHAR ( 66 )  NOT NULL
+);
+CREATE TABLE IF NOT EXISTS {schema}.subscribed_proposed_transaction (
+    time                TIMESTAMP       NOT NULL,
+    size                BIGINT          NOT NULL,
+    cycles              BIGINT          NOT NULL,
+    fee                 BIGINT          NOT NULL,
+    n_inputs            INT             NOT NULL,
+    n_outputs           INT             NOT NULL,
+    n_header_deps       INT             NOT NULL,
+    n_cell_deps         INT             NOT NULL,
+    proposal_id         VARCHAR ( 66 )  NOT NULL,
+    hash                VARCHAR ( 66 )  NOT NULL
+);
+CREATE TABLE IF NOT EXISTS {schema}.subscribed_rejected_transaction (
+    time                TIMESTAMP       NOT NULL,
+    reason              VARCHAR ( 60 )  NOT NULL,
+    size                BIGINT          NOT NULL,
+    cycles              BIGINT          NOT NULL,
+    fee                 BIGINT          NOT NULL,
+    n_inputs            INT             NOT NULL,
+    n_outputs           INT             NOT NULL,
+    n_header_deps       INT             NOT NULL,
+    n_cell_deps         INT             NOT NULL,
+    proposal_id         VARCHAR ( 66 )  NOT NULL,
+    hash                VARCHAR ( 66 )  NOT NULL
+);
+CREATE TABLE IF NOT EXISTS {schema}.epoch (
+    start_time          TIMESTAMP       NOT NULL,
+    end_time            TIMESTAMP       NOT NULL,
+    number              BIGINT          NOT NULL,
+    length              BIGINT          NOT NULL,
+    start_number        BIGINT          NOT NULL,
+    n_uncles            INT             NOT NULL,
+    difficulty          NUMERIC         NOT NULL
+);
+CREATE TABLE IF NOT EXISTS {schema}.retention_transaction (
+    time                TIMESTAMP       NOT NULL,
+    hash                VARCHAR ( 66 )  NOT NULL
+);
+CREATE TABLE IF NOT EXISTS {schema}.created_cell (
+    time                   TIMESTAMP       NOT NULL,
+    block_number           BIGINT          NOT NULL,
+    tx_index               INT             NOT NULL,
+    tx_hash                VARCHAR ( 66 )  NOT NULL,
+    index                  INT             NOT NULL,
+    lock_hash_type         INT             NOT NULL,
+    lock_code_hash         VARCHAR ( 66 )  NOT NULL,
+    lock_args              VARCHAR ( 100 ),
+    type_hash_type         INT,
+    type_code_hash         VARCHAR ( 66 ),
+    PRIMARY KEY (tx_hash, index)
+);
+CREATE TABLE IF NOT EXISTS {schema}.spent_cell (
+    time                   TIMESTAMP       NOT NULL,
+    block_number           BIGINT          NOT NULL,
+    tx_hash                VARCHAR ( 66 )  NOT NULL,
+    index                  BIGINT          NOT NULL,
+    PRIMARY KEY (tx_hash, index)
+);
+CREATE TABLE IF NOT EXISTS {schema}.compact_block_first_seen (
+    time                        TIMESTAMP       NOT NULL,
+    block_number                BIGINT          NOT NULL,
+    ip                          VARCHAR ( 46 )  NOT NULL
+);
+CREATE TABLE IF NOT EXISTS {schema}.peer_last_compact_block (
+    ip                          VARCHAR ( 46 )  PRIMARY KEY NOT NULL,
+    time                        TIMESTAMP       NOT NULL,
+    block_number                BIGINT          NOT NULL,
+    block_hash                  VARCHAR ( 66 )  NOT NULL
+);
@@ -0,0 +1,19 @@
+-- Chain reorganizations detected by ChainCrawler and CellCrawler; their orphaned rows are
+-- deleted by block number, hence the indexes.
+
+CREATE TABLE IF NOT EXISTS {schema}.reorg (
+    time                TIMESTAMP       NOT NULL,
+    topic               TEXT            NOT NULL,
+    fork_number         BIGINT          NOT NULL,
+    depth               BIGINT          NOT NULL,
+    old_tip_number      BIGINT          NOT NULL,
+    old_tip_hash        VARCHAR ( 66 )  NOT NULL,
+    new_tip_hash        VARCHAR ( 66 )  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS created_cell_block_number ON {schema}.created_cell (block_number);
+CREATE INDEX IF NOT EXISTS spent_cell_block_number ON {schema}.spent_cell (block_number);
+
+-- The block hashes of the created cells seed CellCrawler's reorg detection on restart; the rows
+-- inserted before are left NULL.
+ALTER TABLE {schema}.created_cell ADD COLUMN IF NOT EXISTS block_hash VARCHAR ( 66 ) NULL;
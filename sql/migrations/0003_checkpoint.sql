@@ -0,0 +1,15 @@
+-- Progress of the height-based crawlers, keyed by topic within the schema of a network. A
+-- checkpoint is written in the same transaction as, or after, the rows of its block/epoch.
+
+CREATE TABLE IF NOT EXISTS {schema}.checkpoint (
+    topic               TEXT            PRIMARY KEY NOT NULL,
+    number              BIGINT          NOT NULL,
+    time                TIMESTAMP       NOT NULL
+);
+
+-- Epochs are keyed by number, so that EpochCrawler re-inserting the epoch after its checkpoint
+-- on restart is a no-op. The duplicates inserted before are dropped first.
+
+DELETE FROM {schema}.epoch a USING {schema}.epoch b
+    WHERE a.number = b.number AND a.ctid > b.ctid;
+CREATE UNIQUE INDEX IF NOT EXISTS epoch_number ON {schema}.epoch (number);
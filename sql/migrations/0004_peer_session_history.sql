@@ -0,0 +1,14 @@
+-- Peers joining and leaving the online set observed by NetworkCrawler. `uptime` is the length
+-- in seconds of the session ended by a leave event, NULL on join events.
+
+CREATE TABLE IF NOT EXISTS {schema}.peer_session_history (
+    time                TIMESTAMP       NOT NULL,
+    event               TEXT            NOT NULL,
+    ip                  TEXT            NOT NULL,
+    address             TEXT            NOT NULL,
+    peer_id             TEXT            NOT NULL,
+    joined_at           TIMESTAMP       NOT NULL,
+    uptime              BIGINT          NULL
+);
+
+CREATE INDEX IF NOT EXISTS peer_session_history_time ON {schema}.peer_session_history (time);
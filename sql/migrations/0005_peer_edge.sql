@@ -0,0 +1,12 @@
+-- Reachability graph collected by NetworkCrawler: `src_ip` advertised `dst_ip` in its Discovery
+-- `Nodes` responses, between `first_seen` and `last_seen`.
+
+CREATE TABLE IF NOT EXISTS {schema}.peer_edge (
+    src_ip              TEXT            NOT NULL,
+    dst_ip              TEXT            NOT NULL,
+    first_seen          TIMESTAMP       NOT NULL,
+    last_seen           TIMESTAMP       NOT NULL,
+    PRIMARY KEY (src_ip, dst_ip)
+);
+
+CREATE INDEX IF NOT EXISTS peer_edge_dst_ip ON {schema}.peer_edge (dst_ip);
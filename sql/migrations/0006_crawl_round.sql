@@ -0,0 +1,11 @@
+-- Crawl coverage of NetworkCrawler, one row per round of 10 minutes.
+
+CREATE TABLE IF NOT EXISTS {schema}.crawl_round (
+    time                TIMESTAMP       NOT NULL,
+    n_known             INT             NOT NULL,
+    n_dialed            INT             NOT NULL,
+    n_succeeded         INT             NOT NULL,
+    n_failures          INT             NOT NULL,
+    n_never_dialed      INT             NOT NULL,
+    n_backing_off       INT             NOT NULL
+);
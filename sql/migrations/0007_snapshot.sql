@@ -0,0 +1,29 @@
+-- Immutable full crawls of the network taken by `ckb-analyzer snapshot`. The `snapshot` row is
+-- written after its peers and edges, so a snapshot without it is incomplete.
+
+CREATE TABLE IF NOT EXISTS {schema}.snapshot (
+    id                  BIGINT          PRIMARY KEY NOT NULL,
+    started_at          TIMESTAMP       NOT NULL,
+    finished_at         TIMESTAMP       NOT NULL,
+    complete            BOOLEAN         NOT NULL,
+    n_peers             INT             NOT NULL,
+    n_edges             INT             NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS {schema}.snapshot_peer (
+    snapshot_id         BIGINT          NOT NULL,
+    ip                  TEXT            NOT NULL,
+    address             TEXT            NOT NULL,
+    peer_id             TEXT            NOT NULL,
+    version             TEXT            NOT NULL,
+    node_type           INT             NOT NULL,
+    n_reachable         INT             NOT NULL,
+    PRIMARY KEY (snapshot_id, ip)
+);
+
+CREATE TABLE IF NOT EXISTS {schema}.snapshot_edge (
+    snapshot_id         BIGINT          NOT NULL,
+    src_ip              TEXT            NOT NULL,
+    dst_ip              TEXT            NOT NULL,
+    PRIMARY KEY (snapshot_id, src_ip, dst_ip)
+);
@@ -0,0 +1,17 @@
+-- Latest Identify payload per peer address, and the ids of the protocols its session opened.
+-- `services` are the names of the bits set in `flag`.
+
+CREATE TABLE IF NOT EXISTS {schema}.peer_identify (
+    time                TIMESTAMP       NOT NULL,
+    ip                  TEXT            NOT NULL,
+    address             TEXT            PRIMARY KEY NOT NULL,
+    name                TEXT            NOT NULL,
+    client_version      TEXT            NOT NULL,
+    flag                BIGINT          NOT NULL,
+    services            TEXT[]          NOT NULL,
+    listen_addrs        TEXT[]          NOT NULL,
+    observed_addr       TEXT            NOT NULL,
+    protocols           INT[]           NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS peer_identify_client_version ON {schema}.peer_identify (client_version);
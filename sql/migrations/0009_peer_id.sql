@@ -0,0 +1,28 @@
+-- Peers are identified by their PeerId instead of their address: one row per node, holding all
+-- its observed addresses.
+--
+-- `peer_id` used to be the last component of the address, which is not a PeerId for addresses
+-- without `/p2p/`; those are cleared, and the rows of one PeerId are merged into the latest.
+
+ALTER TABLE {schema}.peer ADD COLUMN IF NOT EXISTS addresses TEXT[] NOT NULL DEFAULT '{}';
+UPDATE {schema}.peer SET addresses = ARRAY[address] WHERE address IS NOT NULL AND addresses = '{}';
+UPDATE {schema}.peer SET peer_id = NULL WHERE peer_id !~ '^[1-9A-HJ-NP-Za-km-z]{40,60}$';
+
+UPDATE {schema}.peer AS latest
+SET addresses = merged.addresses
+FROM (
+    SELECT peer_id, ARRAY(SELECT DISTINCT unnest(array_agg(address))) AS addresses
+    FROM {schema}.peer
+    WHERE peer_id IS NOT NULL AND address IS NOT NULL
+    GROUP BY peer_id
+) AS merged
+WHERE latest.peer_id = merged.peer_id;
+DELETE FROM {schema}.peer AS a USING {schema}.peer AS b
+WHERE a.peer_id = b.peer_id AND (a.time, a.id) < (b.time, b.id);
+
+ALTER TABLE {schema}.peer DROP CONSTRAINT IF EXISTS unique_address;
+ALTER TABLE {schema}.peer ADD CONSTRAINT unique_peer_id UNIQUE (peer_id);
+
+-- One row per node within a snapshot as well
+ALTER TABLE {schema}.snapshot_peer DROP CONSTRAINT IF EXISTS snapshot_peer_pkey;
+ALTER TABLE {schema}.snapshot_peer ADD PRIMARY KEY (snapshot_id, peer_id);
@@ -0,0 +1,11 @@
+-- Crawl coverage of NetworkCrawler per transport, one row per transport and round of 10 minutes.
+
+CREATE TABLE IF NOT EXISTS {schema}.transport_reachability (
+    time                TIMESTAMP       NOT NULL,
+    transport           TEXT            NOT NULL,
+    n_known             INT             NOT NULL,
+    n_dialed            INT             NOT NULL,
+    n_succeeded         INT             NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS transport_reachability_time ON {schema}.transport_reachability (time);
@@ -0,0 +1,14 @@
+-- Outcomes of probing the observed addresses with a TCP connection and a secio handshake, one
+-- row per probe. `outcome` is one of reachable, refused, timed_out, wrong_network and failed;
+-- `latency` is the TCP connect time in milliseconds of reachable addresses.
+
+CREATE TABLE IF NOT EXISTS {schema}.address_probe (
+    time                TIMESTAMP       NOT NULL,
+    ip                  TEXT            NOT NULL,
+    address             TEXT            NOT NULL,
+    peer_id             TEXT            NULL,
+    outcome             TEXT            NOT NULL,
+    latency             INT             NULL
+);
+
+CREATE INDEX IF NOT EXISTS address_probe_address_time ON {schema}.address_probe (address, time);
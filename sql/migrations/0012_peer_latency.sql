@@ -0,0 +1,15 @@
+-- Latency percentiles of each peer in milliseconds, one row per peer, metric and round of 10
+-- minutes. `metric` is one of connect, handshake, identify and ping.
+
+CREATE TABLE IF NOT EXISTS {schema}.peer_latency (
+    time                TIMESTAMP           NOT NULL,
+    peer_id             TEXT                NOT NULL,
+    ip                  TEXT                NOT NULL,
+    metric              TEXT                NOT NULL,
+    n_samples           INT                 NOT NULL,
+    p50                 DOUBLE PRECISION    NOT NULL,
+    p90                 DOUBLE PRECISION    NOT NULL,
+    p99                 DOUBLE PRECISION    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS peer_latency_peer_id_time ON {schema}.peer_latency (peer_id, time);
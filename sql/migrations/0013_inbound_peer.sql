@@ -0,0 +1,17 @@
+-- Peers that dialed NetworkCrawler in listening mode, one row per PeerId. `reachable` tells
+-- whether it could be dialed back, at `dialback_addr`; the unreachable ones are mostly NATed.
+
+CREATE TABLE IF NOT EXISTS {schema}.inbound_peer (
+    peer_id             TEXT            PRIMARY KEY,
+    first_seen          TIMESTAMP       NOT NULL,
+    last_seen           TIMESTAMP       NOT NULL,
+    ip                  TEXT            NOT NULL,
+    address             TEXT            NOT NULL,
+    name                TEXT            NOT NULL,
+    client_version      TEXT            NOT NULL,
+    flag                BIGINT          NOT NULL,
+    services            TEXT[]          NOT NULL,
+    listen_addrs        TEXT[]          NOT NULL,
+    reachable           BOOLEAN         NOT NULL,
+    dialback_addr       TEXT            NULL
+);
@@ -0,0 +1,9 @@
+-- Autonomous system of each IP, from the ASN CSV files. `provider` names the well-known cloud or
+-- hosting provider operating it, and `hosting_class` is one of cloud, hosting and residential.
+
+ALTER TABLE {schema}.ipinfo ADD COLUMN IF NOT EXISTS asn BIGINT NULL;
+ALTER TABLE {schema}.ipinfo ADD COLUMN IF NOT EXISTS as_organization TEXT NULL;
+ALTER TABLE {schema}.ipinfo ADD COLUMN IF NOT EXISTS provider TEXT NULL;
+ALTER TABLE {schema}.ipinfo ADD COLUMN IF NOT EXISTS hosting_class TEXT NULL;
+
+CREATE INDEX IF NOT EXISTS ipinfo_asn ON {schema}.ipinfo (asn);
@@ -0,0 +1,16 @@
+-- Geographic distribution of each snapshot, for maps: one row per snapshot, city and version,
+-- with the number of peers. Cities are told apart by region too, e.g. Portland, Oregon and
+-- Portland, Maine. Peers that could not be geolocated are counted under country, region and
+-- city ''.
+
+CREATE TABLE IF NOT EXISTS {schema}.geo_city (
+    snapshot_id         BIGINT              NOT NULL,
+    country             TEXT                NOT NULL,
+    region              TEXT                NOT NULL,
+    city                TEXT                NOT NULL,
+    latitude            DOUBLE PRECISION    NULL,
+    longitude           DOUBLE PRECISION    NULL,
+    version             TEXT                NOT NULL,
+    n_nodes             INT                 NOT NULL,
+    PRIMARY KEY (snapshot_id, country, region, city, version)
+);
@@ -0,0 +1,32 @@
+-- Block propagation measured by CompactBlockCrawler, delays in milliseconds.
+--
+-- `block_propagation_delay` holds the first arrival of each block from each peer, relative to
+-- the first sighting of the block and to its header timestamp. `block_propagation` summarizes
+-- the arrivals of a block within 1 minute after its first sighting, at `time`.
+
+CREATE TABLE IF NOT EXISTS {schema}.block_propagation_delay (
+    time                TIMESTAMP       NOT NULL,
+    block_number        BIGINT          NOT NULL,
+    block_hash          VARCHAR ( 66 )  NOT NULL,
+    ip                  VARCHAR ( 46 )  NOT NULL,
+    delay               BIGINT          NOT NULL,
+    header_delay        BIGINT          NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS block_propagation_delay_block_number ON {schema}.block_propagation_delay (block_number);
+
+CREATE TABLE IF NOT EXISTS {schema}.block_propagation (
+    time                TIMESTAMP       NOT NULL,
+    block_number        BIGINT          NOT NULL,
+    block_hash          VARCHAR ( 66 )  PRIMARY KEY NOT NULL,
+    first_ip            VARCHAR ( 46 )  NOT NULL,
+    n_peers             INT             NOT NULL,
+    p50                 BIGINT          NOT NULL,
+    p90                 BIGINT          NOT NULL,
+    p99                 BIGINT          NOT NULL,
+    header_p50          BIGINT          NOT NULL,
+    header_p90          BIGINT          NOT NULL,
+    header_p99          BIGINT          NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS block_propagation_time ON {schema}.block_propagation (time);
@@ -1,51 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
+use std::time::Duration;
 use serde::{Deserialize};
 use crate::{
     topic::CKBNetworkType
 };
 
-
+/// NetworkCrawler forgets the addresses unseen for this long, unless `prune_window` is set
+pub const DEFAULT_PRUNE_WINDOW: Duration = Duration::from_secs(60 * 60 * 24);
+/// NetworkCrawler keeps at most this many dials pending, unless `dial_concurrency` is set
+pub const DEFAULT_DIAL_CONCURRENCY: usize = 16;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct CKBAnalyzerConfig {
-    pub networks: Vec<CKBNetworkType>,
-    pub db: DBConfig,
-    pub witness_bound: usize,
+    pub networks: Vec<NetworkConfig>,
+    pub db: Option<DBConfig>,
+    pub sink: SinkConfig,
+    pub prune_window: Duration,
+    pub dial_concurrency: usize,
+    // GeoLite2 city CSV files to geolocate peer IPs with, see `util::geo`
+    pub geoip_csv: Vec<PathBuf>,
+    // Look up ipinfo.io for the IPs unknown to `geoip_csv`
+    pub ipinfo_fallback: bool,
+    // ASN CSV files to look up the autonomous systems of peer IPs with, see `util::asn`
+    pub asn_csv: Vec<PathBuf>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 struct RawCKBAnalyzerConfig {
-    networks: Vec<String>,
-    db: DBConfig,
-    witness_bound: usize,
+    networks: RawNetworks,
+    db: Option<DBConfig>,
+    #[serde(default)]
+    sink: SinkConfig,
+    // In seconds
+    #[serde(default = "default_prune_window")]
+    prune_window: u64,
+    #[serde(default = "default_dial_concurrency")]
+    dial_concurrency: usize,
+    #[serde(default)]
+    geoip_csv: Vec<PathBuf>,
+    #[serde(default = "default_ipinfo_fallback")]
+    ipinfo_fallback: bool,
+    #[serde(default)]
+    asn_csv: Vec<PathBuf>,
+    // Deprecated, addresses failing to be dialed count as online only if probed reachable
+    witness_bound: Option<u64>,
+}
+
+/// `networks` accepts both the legacy list form, `networks = ["mirana", "pudge"]`,
+/// and the table form, `[networks.mirana]`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum RawNetworks {
+    Names(Vec<String>),
+    Tables(BTreeMap<String, RawNetworkConfig>),
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct RawNetworkConfig {
+    rpc_url: Option<String>,
+    subscription_addr: Option<String>,
+    topics: Option<Vec<String>>,
+    #[serde(default)]
+    intervals: HashMap<String, u64>,
+    listen_address: Option<String>,
+    public_address: Option<String>,
+}
+
+/// Per-network settings, declared as `[networks.<name>]`
+#[derive(Clone, Debug, Deserialize)]
+pub struct NetworkConfig {
+    pub network_type: CKBNetworkType,
+    // CKB node RPC endpoint, required by RPC-driven topics
+    pub rpc_url: Option<String>,
+    // CKB node TCP subscription address, required by Subscribe* topics
+    pub subscription_addr: Option<String>,
+    // Topics enabled on this network, fallback to `--topics` if absent
+    pub topics: Option<Vec<String>>,
+    // #{ topic => interval in seconds }
+    pub intervals: HashMap<String, u64>,
+    // Multiaddr NetworkCrawler listens on for inbound peers, not listening if absent
+    pub listen_address: Option<String>,
+    // Multiaddr NetworkCrawler advertises to the peers when listening
+    pub public_address: Option<String>,
 }
 
 impl CKBAnalyzerConfig {
-    pub fn new(networks : Vec<CKBNetworkType>, db : DBConfig, ipinfo_io_token: String, witness_bound: usize) -> Self {
+    pub fn new(networks : Vec<NetworkConfig>, db : DBConfig, ipinfo_io_token: String) -> Self {
         Self {
             networks,
-            db,
-            witness_bound,
+            db: Some(db),
+            sink: SinkConfig::default(),
+            prune_window: DEFAULT_PRUNE_WINDOW,
+            dial_concurrency: DEFAULT_DIAL_CONCURRENCY,
+            geoip_csv: Vec::new(),
+            ipinfo_fallback: true,
+            asn_csv: Vec::new(),
         }
     }
 
     pub fn from_file(f: PathBuf) -> Self {
-        let raw_config: RawCKBAnalyzerConfig = toml::from_str(&std::fs::read_to_string(f).unwrap()).unwrap();
+        Self::from_toml(&std::fs::read_to_string(f).unwrap())
+    }
+
+    pub fn from_toml(s: &str) -> Self {
+        let raw_config: RawCKBAnalyzerConfig = toml::from_str(s).unwrap();
         Self::from(raw_config)
     }
 }
 
 impl From<RawCKBAnalyzerConfig> for CKBAnalyzerConfig {
     fn from(raw: RawCKBAnalyzerConfig) -> Self {
-        let networks = raw.networks.into_iter().map(|s| CKBNetworkType::from(s)).collect();
+        if raw.witness_bound.is_some() {
+            log::warn!("`witness_bound` is deprecated and ignored, addresses failing to be dialed count as online only if probed reachable");
+        }
+        let networks = match raw.networks {
+            RawNetworks::Names(names) => names
+                .into_iter()
+                .map(|name| NetworkConfig::new(CKBNetworkType::from(name)))
+                .collect(),
+            RawNetworks::Tables(tables) => tables
+                .into_iter()
+                .map(|(name, raw)| NetworkConfig {
+                    network_type: CKBNetworkType::from(name),
+                    rpc_url: raw.rpc_url,
+                    subscription_addr: raw.subscription_addr,
+                    topics: raw.topics,
+                    intervals: raw.intervals,
+                    listen_address: raw.listen_address,
+                    public_address: raw.public_address,
+                })
+                .collect(),
+        };
         Self {
             networks,
             db: raw.db,
-            witness_bound: raw.witness_bound,
+            sink: raw.sink,
+            prune_window: Duration::from_secs(raw.prune_window),
+            dial_concurrency: raw.dial_concurrency,
+            geoip_csv: raw.geoip_csv,
+            ipinfo_fallback: raw.ipinfo_fallback,
+            asn_csv: raw.asn_csv,
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn new(network_type: CKBNetworkType) -> Self {
+        Self {
+            network_type,
+            rpc_url: None,
+            subscription_addr: None,
+            topics: None,
+            intervals: HashMap::new(),
+            listen_address: None,
+            public_address: None,
+        }
+    }
+
+    /// Return the configured interval of `topic`, or its default one
+    pub fn interval(&self, topic: &str) -> Duration {
+        self.intervals
+            .get(topic)
+            .map(|secs| Duration::from_secs(*secs))
+            .unwrap_or_else(|| default_interval(topic))
+    }
+}
+
+fn default_interval(topic: &str) -> Duration {
+    match topic {
+        "EpochCrawler" => Duration::from_secs(10),
+        "PoolCrawler" => Duration::from_secs(5),
+        "RetentionTransactionCrawler" => Duration::from_secs(60 * 10),
+        _ => Duration::from_secs(1),
+    }
+}
+
+fn default_prune_window() -> u64 {
+    DEFAULT_PRUNE_WINDOW.as_secs()
+}
+
+fn default_ipinfo_fallback() -> bool {
+    true
+}
+
+fn default_dial_concurrency() -> usize {
+    DEFAULT_DIAL_CONCURRENCY
+}
+
+/// Where entries go, declared as `[sink]`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    // Write into Postgres, connected by `[db]` or the `PG*` environment variables. Entries
+    // failing permanently are appended to `dead_letter_path`. While the database is
+    // unreachable, up to `backlog_capacity` entries are buffered in memory, the overflow goes
+    // to `spill_path` if set, otherwise it is dropped. Pending schema migrations are applied
+    // on start if `auto_migrate`, otherwise the analyzer refuses to start.
+    Postgres {
+        #[serde(default = "default_dead_letter_path")]
+        dead_letter_path: PathBuf,
+        #[serde(default = "default_backlog_capacity")]
+        backlog_capacity: usize,
+        spill_path: Option<PathBuf>,
+        #[serde(default = "default_auto_migrate")]
+        auto_migrate: bool,
+    },
+    // Append entries to `path` as newline-delimited JSON
+    JsonLines { path: PathBuf },
+    // Keep the latest `capacity` entries in memory, for dry runs
+    Memory {
+        #[serde(default = "default_memory_capacity")]
+        capacity: usize,
+    },
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        SinkConfig::Postgres {
+            dead_letter_path: default_dead_letter_path(),
+            backlog_capacity: default_backlog_capacity(),
+            spill_path: None,
+            auto_migrate: default_auto_migrate(),
         }
     }
 }
 
+fn default_dead_letter_path() -> PathBuf {
+    PathBuf::from("dead_letter.jsonl")
+}
+
+fn default_backlog_capacity() -> usize {
+    100000
+}
+
+fn default_auto_migrate() -> bool {
+    true
+}
+
+fn default_memory_capacity() -> usize {
+    10000
+}
+
 #[derive(Clone, Debug, Deserialize)]
 // for postgresql connection
 pub struct DBConfig {
@@ -67,3 +263,90 @@ impl DBConfig {
         }
     }
 }
+
+#[test]
+fn test_parse_networks() {
+    let db = r#"
+        [db]
+        host = "postgresql"
+        port = 5432
+        user = "postgres"
+        password = "postgres"
+        database = "ckb"
+    "#;
+
+    let legacy = CKBAnalyzerConfig::from_toml(&format!(
+        "networks = [\"mirana\", \"pudge\"]\nwitness_bound = 3\n{}",
+        db
+    ));
+    assert_eq!(legacy.networks.len(), 2);
+    assert_eq!(legacy.networks[0].network_type, CKBNetworkType::Mirana);
+    assert!(legacy.networks[0].rpc_url.is_none());
+    assert!(legacy.networks[0].topics.is_none());
+    assert_eq!(legacy.prune_window, DEFAULT_PRUNE_WINDOW);
+    assert_eq!(legacy.dial_concurrency, DEFAULT_DIAL_CONCURRENCY);
+    assert!(legacy.geoip_csv.is_empty());
+    assert!(legacy.ipinfo_fallback);
+    assert!(legacy.asn_csv.is_empty());
+
+    let tables = CKBAnalyzerConfig::from_toml(&format!(
+        r#"
+        witness_bound = 3
+        prune_window = 3600
+        geoip_csv = ["geolite2-city-ipv4.csv"]
+        ipinfo_fallback = false
+        asn_csv = ["asn-ipv4.csv", "asn-ipv6.csv"]
+
+        [networks.mirana]
+        rpc_url = "http://127.0.0.1:8114"
+        subscription_addr = "127.0.0.1:18114"
+        topics = ["ChainCrawler", "PoolCrawler"]
+        intervals = {{ PoolCrawler = 30 }}
+
+        [networks.pudge]
+        topics = ["NetworkCrawler"]
+        {}
+        "#,
+        db
+    ));
+    assert_eq!(tables.networks.len(), 2);
+    assert_eq!(tables.prune_window, Duration::from_secs(3600));
+    assert_eq!(tables.geoip_csv, vec![PathBuf::from("geolite2-city-ipv4.csv")]);
+    assert!(!tables.ipinfo_fallback);
+    assert_eq!(tables.asn_csv.len(), 2);
+    let mirana = &tables.networks[0];
+    assert_eq!(mirana.network_type, CKBNetworkType::Mirana);
+    assert_eq!(mirana.rpc_url.as_deref(), Some("http://127.0.0.1:8114"));
+    assert_eq!(mirana.subscription_addr.as_deref(), Some("127.0.0.1:18114"));
+    assert_eq!(mirana.interval("PoolCrawler"), Duration::from_secs(30));
+    assert_eq!(mirana.interval("EpochCrawler"), Duration::from_secs(10));
+    let pudge = &tables.networks[1];
+    assert_eq!(pudge.network_type, CKBNetworkType::Pudge);
+    assert_eq!(pudge.topics, Some(vec!["NetworkCrawler".to_string()]));
+}
+
+#[test]
+fn test_parse_sink() {
+    let config = CKBAnalyzerConfig::from_toml(
+        r#"
+        networks = ["mirana"]
+        witness_bound = 3
+
+        [sink]
+        type = "json_lines"
+        path = "entries.jsonl"
+        "#,
+    );
+    assert!(config.db.is_none());
+    assert!(matches!(config.sink, SinkConfig::JsonLines { ref path } if path == &PathBuf::from("entries.jsonl")));
+
+    let config = CKBAnalyzerConfig::from_toml("networks = [\"mirana\"]\nwitness_bound = 3\n[sink]\ntype = \"memory\"");
+    assert!(matches!(config.sink, SinkConfig::Memory { capacity: 10000 }));
+
+    let config = CKBAnalyzerConfig::from_toml("networks = [\"mirana\"]\nwitness_bound = 3\n[sink]\ntype = \"postgres\"");
+    assert!(matches!(
+        config.sink,
+        SinkConfig::Postgres { ref dead_letter_path, backlog_capacity: 100000, spill_path: None, auto_migrate: true }
+            if dead_letter_path == &PathBuf::from("dead_letter.jsonl")
+    ));
+}
@@ -3,22 +3,191 @@ use crate::ckb_types::{
     core::ScriptHashType,
     packed::{Byte32, OutPoint, ProposalShortId},
 };
+use serde::{Deserialize, Serialize};
 
 /// Peer info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Peer {
     pub network: String,
     pub time: chrono::NaiveDateTime,
     pub version: String,
     pub ip: String,
     pub n_reachable: i32,
+    // The latest address
     pub address: String,
+    pub addresses: Vec<String>,
+    // Base58 PeerId authenticated by the session
     pub peer_id: String,
     pub node_type: u8,
 }
 
+/// Peer joining ("join") or leaving ("leave") the online peers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerSession {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub event: String,
+    pub ip: String,
+    pub address: String,
+    pub peer_id: String,
+    pub joined_at: chrono::NaiveDateTime,
+    // Session length in seconds, on leave
+    pub uptime: Option<i64>,
+}
+
+/// `src_ip` advertised `dst_ip` through Discovery
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerEdge {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub src_ip: String,
+    pub dst_ip: String,
+}
+
+/// Crawl coverage of NetworkCrawler within a round
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CrawlRound {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub n_known: i32,
+    pub n_dialed: i32,
+    pub n_succeeded: i32,
+    pub n_failures: i32,
+    pub n_never_dialed: i32,
+    pub n_backing_off: i32,
+}
+
+/// Crawl coverage of the addresses of a transport, e.g. `ipv4` or `dns4`, within a round
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransportReachability {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub transport: String,
+    pub n_known: i32,
+    pub n_dialed: i32,
+    pub n_succeeded: i32,
+}
+
+/// Outcome of probing an address with a TCP connection and a secio handshake
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AddressProbe {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub ip: String,
+    pub address: String,
+    // PeerId authenticated by the handshake
+    pub peer_id: Option<String>,
+    // "reachable", "refused", "timed_out", "wrong_network" or "failed"
+    pub outcome: String,
+    // TCP connect time in milliseconds, of reachable addresses
+    pub latency: Option<i32>,
+}
+
+/// Latency percentiles of a peer within a crawl round, in milliseconds. `metric` is one of
+/// "connect" and "handshake" measured by probes, "identify" since the session opened, and
+/// "ping" round-trip time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerLatency {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub peer_id: String,
+    pub ip: String,
+    pub metric: String,
+    pub n_samples: i32,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+/// A peer that dialed the analyzer in listening mode, and whether it could be dialed back
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InboundPeer {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub peer_id: String,
+    pub ip: String,
+    // The address it connected from
+    pub address: String,
+    pub name: String,
+    pub client_version: String,
+    pub flag: i64,
+    pub services: Vec<String>,
+    pub listen_addrs: Vec<String>,
+    pub reachable: bool,
+    // The address it was dialed back at
+    pub dialback_addr: Option<String>,
+}
+
+/// Full crawl of the network by `ckb-analyzer snapshot`, written after its peers and edges
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub network: String,
+    pub id: i64,
+    pub started_at: chrono::NaiveDateTime,
+    pub finished_at: chrono::NaiveDateTime,
+    // Whether the frontier was exhausted before the deadline
+    pub complete: bool,
+    pub n_peers: i32,
+    pub n_edges: i32,
+}
+
+/// Peer identified within a snapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotPeer {
+    pub network: String,
+    pub snapshot_id: i64,
+    pub ip: String,
+    pub address: String,
+    pub peer_id: String,
+    pub version: String,
+    pub node_type: u8,
+    pub n_reachable: i32,
+}
+
+/// `src_ip` advertised `dst_ip` within a snapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotEdge {
+    pub network: String,
+    pub snapshot_id: i64,
+    pub src_ip: String,
+    pub dst_ip: String,
+}
+
+/// Number of peers of a city running a version within a snapshot. Peers that could not be
+/// geolocated fall into the city "" of the country "".
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeoCity {
+    pub network: String,
+    pub snapshot_id: i64,
+    pub country: String,
+    pub region: String,
+    pub city: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub version: String,
+    pub n_nodes: i32,
+}
+
+/// Identify payload of a session, and the protocols it opened
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerIdentify {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub ip: String,
+    pub address: String,
+    pub name: String,
+    pub client_version: String,
+    pub flag: i64,
+    // Names of the services set in `flag`
+    pub services: Vec<String>,
+    pub listen_addrs: Vec<String>,
+    // Our address observed by the peer
+    pub observed_addr: String,
+    pub protocols: Vec<i32>,
+}
+
 /// IP info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IpInfo {
     pub network: String,
     pub ip: String,
@@ -26,10 +195,18 @@ pub struct IpInfo {
     pub city: String,
     pub region: String,
     pub company: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // Autonomous system, see `util::asn`
+    pub asn: Option<i64>,
+    pub as_organization: Option<String>,
+    pub provider: Option<String>,
+    // "cloud", "hosting" or "residential"
+    pub hosting_class: Option<String>,
 }
 
 /// Block Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Block {
     pub network: String,
     pub time: chrono::NaiveDateTime,
@@ -41,11 +218,12 @@ pub struct Block {
     pub cellbase_miner_source: String,
     pub miner_lock_args: String,
     pub interval: i64, // ms
+    #[serde(with = "serde_packed")]
     pub hash: Byte32,
 }
 
 /// Epoch Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Epoch {
     pub network: String,
     pub start_time: chrono::NaiveDateTime,
@@ -58,7 +236,7 @@ pub struct Epoch {
 }
 
 /// Tx Pool Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TxPoolInfo {
     pub network: String,
     pub time: chrono::NaiveDateTime,
@@ -70,7 +248,7 @@ pub struct TxPoolInfo {
 }
 
 /// Block Transaction Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BlockTransaction {
     pub network: String,
     pub time: chrono::NaiveDateTime,
@@ -86,7 +264,7 @@ pub struct BlockTransaction {
 }
 
 /// Subscribed New Transaction Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SubscribedNewTransaction {
     pub network: String,
     pub time: chrono::NaiveDateTime,
@@ -97,12 +275,14 @@ pub struct SubscribedNewTransaction {
     pub n_outputs: usize,
     pub n_header_deps: usize,
     pub n_cell_deps: usize,
+    #[serde(with = "serde_packed")]
     pub proposal_id: ProposalShortId,
+    #[serde(with = "serde_packed")]
     pub hash: Byte32,
 }
 
 /// Subscribed Proposed Transaction Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SubscribedProposedTransaction {
     pub network: String,
     pub time: chrono::NaiveDateTime,
@@ -113,12 +293,14 @@ pub struct SubscribedProposedTransaction {
     pub n_outputs: usize,
     pub n_header_deps: usize,
     pub n_cell_deps: usize,
+    #[serde(with = "serde_packed")]
     pub proposal_id: ProposalShortId,
+    #[serde(with = "serde_packed")]
     pub hash: Byte32,
 }
 
 /// Subscribed Rejected Transaction Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SubscribedRejectedTransaction {
     pub network: String,
     pub time: chrono::NaiveDateTime,
@@ -130,44 +312,56 @@ pub struct SubscribedRejectedTransaction {
     pub n_outputs: usize,
     pub n_header_deps: usize,
     pub n_cell_deps: usize,
+    #[serde(with = "serde_packed")]
     pub proposal_id: ProposalShortId,
+    #[serde(with = "serde_packed")]
     pub hash: Byte32,
 }
 
 /// Retention Transaction Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RetentionTransaction {
     pub network: String,
     pub time: chrono::NaiveDateTime,
+    #[serde(with = "serde_packed")]
     pub hash: Byte32,
 }
 
 /// Cell Info
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CreatedCell {
     pub network: String,
     pub time: chrono::NaiveDateTime,
     pub block_number: u64,
+    #[serde(with = "serde_packed")]
+    pub block_hash: Byte32,
     pub tx_index: usize,
+    #[serde(with = "serde_packed")]
     pub out_point: OutPoint,
 
+    #[serde(with = "serde_packed")]
     pub lock_hash_type: ScriptHashType,
+    #[serde(with = "serde_packed")]
     pub lock_code_hash: Byte32,
+    #[serde(with = "serde_packed::option")]
     pub lock_args: Option<Bytes>,
+    #[serde(with = "serde_packed::option")]
     pub type_hash_type: Option<ScriptHashType>,
+    #[serde(with = "serde_packed::option")]
     pub type_code_hash: Option<Byte32>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SpentCell {
     pub network: String,
     pub time: chrono::NaiveDateTime,
     pub block_number: u64,
+    #[serde(with = "serde_packed")]
     pub out_point: OutPoint,
 }
 
 /// Compact block first received from
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CompactBlockFirstSeen {
     pub network: String,
     pub time: chrono::NaiveDateTime,
@@ -178,11 +372,305 @@ pub struct CompactBlockFirstSeen {
 /// Peer's last sent compact block
 ///
 /// Note: This table is not time-serie. It should be indexed by ip and keep update in place.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeerLastCompactBlock {
     pub network: String,
     pub ip: String,
+    #[serde(with = "serde_packed")]
+    pub block_hash: Byte32,
+    pub block_number: u64,
+    pub time: chrono::NaiveDateTime,
+}
+
+/// First arrival of a compact block from a peer, delays in milliseconds
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockPropagationDelay {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub block_number: u64,
+    #[serde(with = "serde_packed")]
     pub block_hash: Byte32,
+    pub ip: String,
+    // Since the first sighting of the block
+    pub delay: i64,
+    // Since the header timestamp
+    pub header_delay: i64,
+}
+
+/// Percentiles of the `BlockPropagationDelay`s of a block, in milliseconds
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockPropagation {
+    pub network: String,
+    // First sighting
+    pub time: chrono::NaiveDateTime,
     pub block_number: u64,
+    #[serde(with = "serde_packed")]
+    pub block_hash: Byte32,
+    pub first_ip: String,
+    pub n_peers: i32,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub header_p50: i64,
+    pub header_p90: i64,
+    pub header_p99: i64,
+}
+
+/// Chain reorganization observed by a crawler, the blocks above `fork_number` were orphaned
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reorg {
+    pub network: String,
+    pub time: chrono::NaiveDateTime,
+    pub topic: String,
+    pub fork_number: u64,
+    pub depth: u64,
+    pub old_tip_number: u64,
+    #[serde(with = "serde_packed")]
+    pub old_tip_hash: Byte32,
+    #[serde(with = "serde_packed")]
+    pub new_tip_hash: Byte32,
+}
+
+/// Delete the rows written by `topic` for the blocks above `fork_number`, which were orphaned
+/// by a reorg
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rollback {
+    pub network: String,
+    pub topic: String,
+    pub fork_number: u64,
+}
+
+/// Progress of a height-based crawler: every row of block/epoch `number` and below was sent
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub network: String,
     pub time: chrono::NaiveDateTime,
+    pub topic: String,
+    pub number: u64,
+}
+
+/// Record sent from crawlers to the sink
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Entry {
+    Peer(Peer),
+    PeerSession(PeerSession),
+    PeerEdge(PeerEdge),
+    CrawlRound(CrawlRound),
+    TransportReachability(TransportReachability),
+    AddressProbe(AddressProbe),
+    PeerLatency(PeerLatency),
+    InboundPeer(InboundPeer),
+    PeerIdentify(PeerIdentify),
+    Snapshot(Snapshot),
+    SnapshotPeer(SnapshotPeer),
+    SnapshotEdge(SnapshotEdge),
+    GeoCity(GeoCity),
+    IpInfo(IpInfo),
+    Block(Block),
+    Epoch(Epoch),
+    TxPoolInfo(TxPoolInfo),
+    BlockTransaction(BlockTransaction),
+    SubscribedNewTransaction(SubscribedNewTransaction),
+    SubscribedProposedTransaction(SubscribedProposedTransaction),
+    SubscribedRejectedTransaction(SubscribedRejectedTransaction),
+    RetentionTransaction(RetentionTransaction),
+    CreatedCell(CreatedCell),
+    SpentCell(SpentCell),
+    CompactBlockFirstSeen(CompactBlockFirstSeen),
+    PeerLastCompactBlock(PeerLastCompactBlock),
+    BlockPropagationDelay(BlockPropagationDelay),
+    BlockPropagation(BlockPropagation),
+    Reorg(Reorg),
+    Rollback(Rollback),
+    Checkpoint(Checkpoint),
+    /// Records of one block or epoch followed by the checkpoint of their crawler, sinks write
+    /// them atomically so that the checkpoint never gets ahead of its records. `backfill` is set
+    /// when the crawler lags far behind the tip, sinks may bulk load such records.
+    /// The records are persisted one by one, sinks unwrap them through `Entry::records`.
+    Batch { records: Vec<Entry>, backfill: bool },
+}
+
+impl Entry {
+    /// Return the records carried by this entry, unwrapping `Batch`
+    pub fn records(&self) -> &[Entry] {
+        match self {
+            Entry::Batch { records, .. } => records,
+            entry => std::slice::from_ref(entry),
+        }
+    }
+}
+
+macro_rules! impl_from_for_entry {
+    ($($name:ident),* $(,)?) => {
+        $(
+            impl From<$name> for Entry {
+                fn from(entry: $name) -> Self {
+                    Entry::$name(entry)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_entry!(
+    Peer,
+    PeerSession,
+    PeerEdge,
+    CrawlRound,
+    TransportReachability,
+    AddressProbe,
+    PeerLatency,
+    InboundPeer,
+    PeerIdentify,
+    Snapshot,
+    SnapshotPeer,
+    SnapshotEdge,
+    GeoCity,
+    IpInfo,
+    Block,
+    Epoch,
+    TxPoolInfo,
+    BlockTransaction,
+    SubscribedNewTransaction,
+    SubscribedProposedTransaction,
+    SubscribedRejectedTransaction,
+    RetentionTransaction,
+    CreatedCell,
+    SpentCell,
+    CompactBlockFirstSeen,
+    PeerLastCompactBlock,
+    BlockPropagationDelay,
+    BlockPropagation,
+    Reorg,
+    Rollback,
+    Checkpoint,
+);
+
+/// Serde representations of the packed types carried by entries: hashes and bytes become
+/// 0x-prefixed hex strings, script hash types become numbers.
+mod serde_packed {
+    use crate::ckb_types::{
+        bytes::Bytes,
+        core::ScriptHashType,
+        packed::{self, Byte32, OutPoint, ProposalShortId},
+        prelude::*,
+    };
+    use serde::{
+        de::{DeserializeOwned, Error},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    pub trait Packed: Sized {
+        type Repr: Serialize + DeserializeOwned;
+
+        fn to_repr(&self) -> Self::Repr;
+        fn from_repr(repr: Self::Repr) -> Result<Self, String>;
+    }
+
+    impl Packed for Byte32 {
+        type Repr = String;
+
+        fn to_repr(&self) -> String {
+            encode_hex(self.as_slice())
+        }
+
+        fn from_repr(repr: String) -> Result<Self, String> {
+            Byte32::from_slice(&decode_hex(&repr)?).map_err(|err| err.to_string())
+        }
+    }
+
+    impl Packed for ProposalShortId {
+        type Repr = String;
+
+        fn to_repr(&self) -> String {
+            encode_hex(self.as_slice())
+        }
+
+        fn from_repr(repr: String) -> Result<Self, String> {
+            ProposalShortId::from_slice(&decode_hex(&repr)?).map_err(|err| err.to_string())
+        }
+    }
+
+    impl Packed for Bytes {
+        type Repr = String;
+
+        fn to_repr(&self) -> String {
+            encode_hex(self)
+        }
+
+        fn from_repr(repr: String) -> Result<Self, String> {
+            decode_hex(&repr).map(Bytes::from)
+        }
+    }
+
+    impl Packed for ScriptHashType {
+        type Repr = u8;
+
+        fn to_repr(&self) -> u8 {
+            (*self).into()
+        }
+
+        fn from_repr(repr: u8) -> Result<Self, String> {
+            ScriptHashType::try_from(packed::Byte::new(repr)).map_err(|err| err.to_string())
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct OutPointRepr {
+        tx_hash: String,
+        index: u32,
+    }
+
+    impl Packed for OutPoint {
+        type Repr = OutPointRepr;
+
+        fn to_repr(&self) -> OutPointRepr {
+            OutPointRepr {
+                tx_hash: self.tx_hash().to_repr(),
+                index: self.index().unpack(),
+            }
+        }
+
+        fn from_repr(repr: OutPointRepr) -> Result<Self, String> {
+            Ok(OutPoint::new(Byte32::from_repr(repr.tx_hash)?, repr.index))
+        }
+    }
+
+    pub fn serialize<T: Packed, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        value.to_repr().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: Packed, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        T::from_repr(T::Repr::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use super::Packed;
+        use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<T: Packed, S: Serializer>(
+            value: &Option<T>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            value.as_ref().map(Packed::to_repr).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, T: Packed, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<T>, D::Error> {
+            Option::<T::Repr>::deserialize(deserializer)?
+                .map(T::from_repr)
+                .transpose()
+                .map_err(D::Error::custom)
+        }
+    }
+
+    fn encode_hex(bytes: &[u8]) -> String {
+        format!("0x{}", hex::encode(bytes))
+    }
+
+    fn decode_hex(s: &str) -> Result<Vec<u8>, String> {
+        hex::decode(s.trim_start_matches("0x")).map_err(|err| format!("invalid hex \"{}\": {}", s, err))
+    }
 }
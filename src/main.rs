@@ -1,7 +1,9 @@
 use crate::topic::{CellCrawler, ChainCrawler, ChainTransactionCrawler, CKBNetworkType, CompactBlockCrawler, EpochCrawler, NetworkCrawler, PoolCrawler, RetentionTransactionCrawler, SubscribeNewTransaction, SubscribeProposedTransaction, SubscribeRejectedTransaction};
-use crate::util::crossbeam_channel_to_tokio_channel;
+use crate::sink::Sink;
+use crate::util::{asn::AsnDatabase, crossbeam_channel_to_tokio_channel, enrichment::{self, Enricher, Enrichment}, geo::{self, GeoResolver}, reorg::REORG_DETECTION_WINDOW};
+use crate::ckb_types::packed::Byte32;
 use ckb_testkit::{connector::SharedState, ConnectorBuilder, Node};
-use clap::{crate_version, values_t_or_exit, App, Arg};
+use clap::{crate_version, values_t_or_exit, App, Arg, ArgMatches, SubCommand};
 use std::env;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
@@ -14,156 +16,520 @@ mod entry;
 mod topic;
 mod util;
 mod config;
+mod migration;
+mod sink;
 
-use config::CKBAnalyzerConfig;
+use config::{CKBAnalyzerConfig, DBConfig, NetworkConfig, SinkConfig, DEFAULT_DIAL_CONCURRENCY, DEFAULT_PRUNE_WINDOW};
+use entry::Entry;
 
 #[tokio::main]
 async fn main() {
     let _logger_guard = init_logger();
     log::info!("CKBAnalyzer starting");
     let matches = clap_app().get_matches();
-    let topics = values_t_or_exit!(matches, "topics", String);
-    let networks = values_t_or_exit!(matches, "network", String);
-    //log::info!("Topics: {:?}", topics);
-    log::info!("Networks: {:?}", networks);
     let config = if matches.is_present("config") {
         Some(CKBAnalyzerConfig::from_file(matches.value_of("config").unwrap().into()))
     } else {
         None
     };
     log::info!("Config: {:?}", config);
+    let network_configs = resolve_network_configs(&matches, config.as_ref());
+    log::info!("Networks: {:?}", network_configs);
 
+    let prune_window = config
+        .as_ref()
+        .map(|config| config.prune_window)
+        .unwrap_or(DEFAULT_PRUNE_WINDOW);
+    let dial_concurrency = config
+        .as_ref()
+        .map(|config| config.dial_concurrency)
+        .unwrap_or(DEFAULT_DIAL_CONCURRENCY);
+    let geoip_csv = config.as_ref().map(|config| config.geoip_csv.clone()).unwrap_or_default();
+    let ipinfo_fallback = config.as_ref().map(|config| config.ipinfo_fallback).unwrap_or(true);
+    let asn_csv = config.as_ref().map(|config| config.asn_csv.clone()).unwrap_or_default();
+    let sink_config = config.as_ref().map(|config| config.sink.clone()).unwrap_or_default();
+    let db_config = config.and_then(|config| config.db);
+    let mut sink = sink::open(&sink_config, || postgres_config(db_config))
+        .await
+        .unwrap_or_else(|err| panic!("open sink {:?}, error: {}", sink_config, err));
 
-
-    let mut witness_bound = 3;
-
-    let pg_config = match config {
-        Some(config) => {
-            witness_bound = config.witness_bound;
-            let host = config.db.host;
-            let port = config.db.port;
-            let database = config.db.database;
-            let user = config.db.user;
-            let password = config.db.password;
-
-            // change setting
-            let mut config = tokio_postgres::Config::new();
-            config
-                .host(&host)
-                .port(port)
-                .dbname(&database)
-                .user(&user)
-                .password(&password)
-                .application_name("CKBAnalyzer");
-            config
-        },
-
-        // Use env var if no config presents
-        None => {
-            let host = env::var_os("PGHOST")
-                .or_else(|| env::var_os("POSTGRES_HOST"))
-                .expect("requires environment variable \"PGHOST\" or \"POSTGRES_HOST\"")
-                .to_string_lossy()
-                .to_string();
-            let port = env::var_os("PGPORT")
-                .or_else(|| env::var_os("POSTGRES_PORT"))
-                .expect("requires environment variable \"PGPORT\" or \"POSTGRES_PORT\"")
-                .to_string_lossy()
-                .parse::<u16>()
-                .expect("requires environment variable \"PGPORT\" or \"POSTGRES_PORT\" to be a number");
-            let database = env::var_os("PGDATABASE")
-                .or_else(|| env::var_os("POSTGRES_DB"))
-                .expect("requires environment variable \"PGDATABASE\" or \"POSTGRES_DB\"")
-                .to_string_lossy()
-                .to_string();
-            let user = env::var_os("PGUSER")
-                .or_else(|| env::var_os("POSTGRES_USER"))
-                .expect("requires environment variable \"PGUSER\" or \"POSTGRES_USER\"")
-                .to_string_lossy()
-                .to_string();
-            let password = env::var_os("PGPASSWORD")
-                .or_else(|| env::var_os("POSTGRES_PASSWORD"))
-                .expect("requires environment variable \"PGPASSWORD\" or \"POSTGRES_PASSWORD\"")
-                .to_string_lossy()
-                .to_string();
-
-            let mut config = tokio_postgres::Config::new();
-            config
-                .host(&host)
-                .port(port)
-                .dbname(&database)
-                .user(&user)
-                .password(&password)
-                .application_name("CKBAnalyzer");
-            config
+    if matches.subcommand_matches("migrate").is_some() {
+        for network_config in network_configs.iter() {
+            let schema = network_config.network_type.into_legacy_str();
+            sink.migrate(&schema, true)
+                .await
+                .unwrap_or_else(|err| panic!("migrate schema {}, error: {}", schema, err));
+            log::info!("Schema {} is up to date", schema);
         }
-    };
-
+        return;
+    }
+    let auto_migrate = matches!(sink_config, SinkConfig::Postgres { auto_migrate: true, .. });
+    for network_config in network_configs.iter() {
+        let schema = network_config.network_type.into_legacy_str();
+        sink.migrate(&schema, auto_migrate)
+            .await
+            .unwrap_or_else(|err| panic!("check schema {}, error: {}", schema, err));
+    }
 
-    let pg = {
-        log::info!("Connecting to Postgres, {:?}", pg_config);
-        let (pg, conn) = pg_config.connect(tokio_postgres::NoTls).await.expect("Failed to connect to Postgres");
-        tokio::spawn(async move {
-            if let Err(err) = conn.await {
-                log::error!("postgres connection error: {}", err);
-            }
-        });
-        pg
-    };
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        let path = PathBuf::from(matches.value_of("file").unwrap());
+        let entries = sink::dead_letter::read(&path)
+            .unwrap_or_else(|err| panic!("read dead letter {}, error: {}", path.display(), err));
+        log::info!("Replay {} entries from {}", entries.len(), path.display());
+        sink.write_batch(&entries)
+            .await
+            .unwrap_or_else(|err| panic!("replay {}, error: {}", path.display(), err));
+        sink.flush(SHUTDOWN_FLUSH_TIMEOUT)
+            .await
+            .unwrap_or_else(|err| panic!("replay {}, error: {}", path.display(), err));
+        return;
+    }
 
+    if let Some(matches) = matches.subcommand_matches("snapshot") {
+        let deadline = matches
+            .value_of("deadline")
+            .unwrap()
+            .parse::<u64>()
+            .map(Duration::from_secs)
+            .expect("requires \"--deadline\" to be a number of seconds");
+        let geo_resolver = geo::geo_resolver(&geoip_csv, ipinfo_fallback);
+        for network_config in network_configs.iter() {
+            take_snapshot(sink.as_mut(), network_config.network_type, &geo_resolver, dial_concurrency, deadline).await;
+        }
+        return;
+    }
 
     // start handlers
-    let (query_sender, mut query_receiver) =
-        crossbeam_channel_to_tokio_channel::channel::<String>(5000);
-    let network_types = networks.into_iter().map(|x| CKBNetworkType::from(x)).collect::<Vec<CKBNetworkType>>();
+    let (entry_sender, mut entry_receiver) =
+        crossbeam_channel_to_tokio_channel::channel::<Entry>(5000);
     let mut _connectors = Vec::new();
+    // Started by the first topic in need, then shared
+    let mut enrichment: Option<Enrichment> = None;
+    let mut enrichment = || {
+        enrichment
+            .get_or_insert_with(|| {
+                let resolver = geo::geo_resolver(&geoip_csv, ipinfo_fallback);
+                let asn_database = Arc::new(AsnDatabase::open(&asn_csv));
+                let (enricher, enrichment) = Enricher::new(resolver, asn_database, entry_sender.clone());
+                tokio::spawn(enricher.run());
+                enrichment
+            })
+            .clone()
+    };
 
-    for topic in topics {
-        match topic.as_str() {
-            "NetworkCrawler" => {
-                for network in network_types.iter() {
+    for network_config in network_configs {
+        let network = network_config.network_type;
+        // RPC-driven topics of one network share one node, it is only required when such a
+        // topic is enabled
+        let mut node: Option<Node> = None;
+        let mut rpc_node = || {
+            node.get_or_insert_with(|| {
+                let ckb_rpc_url = network_config.rpc_url.clone().unwrap_or_else(|| {
+                    panic!(
+                        "requires \"rpc_url\" of network {:?}, \"--ckb-rpc-url\" or environment variable \"CKB_RPC_URL\"",
+                        network
+                    )
+                });
+                log::info!("Connecting to CKB node of {:?}, {}", network, ckb_rpc_url);
+                let node = Node::init_from_url(&ckb_rpc_url, PathBuf::default());
+                let node_network = CKBNetworkType::from(node.consensus().id.clone());
+                assert_eq!(
+                    node_network, network,
+                    "CKB node {} belongs to {:?}, but it is configured for {:?}",
+                    ckb_rpc_url, node_network, network
+                );
+                node
+            })
+            .clone()
+        };
+        let subscription_addr = || {
+            network_config.subscription_addr.clone().unwrap_or_else(|| {
+                panic!(
+                    "requires \"subscription_addr\" of network {:?}, \"--ckb-subscription-addr\" or environment variable \"CKB_SUBSCRIPTION_ADDR\"",
+                    network
+                )
+            })
+        };
+
+        for topic in network_config.topics.clone().unwrap_or_default() {
+            let interval = network_config.interval(&topic);
+            match topic.as_str() {
+                "ChainCrawler" => {
+                    let node = rpc_node();
+                    let last_block_number = last_number(sink.as_ref(), &node.consensus().id, "ChainCrawler").await;
+                    log::info!("Start ChainCrawler from block {}", last_block_number);
+                    let recent_hashes = recent_block_hashes(sink.as_ref(), &node.consensus().id, "ChainCrawler", last_block_number).await;
+                    let chain_crawler = ChainCrawler::new(node, entry_sender.clone(), interval);
+                    tokio::spawn(async move {
+                        chain_crawler.run(last_block_number, recent_hashes).await;
+                    });
+                }
+                "EpochCrawler" => {
+                    let node = rpc_node();
+                    let last_epoch_number = last_number(sink.as_ref(), &node.consensus().id, "EpochCrawler").await;
+                    log::info!("Start EpochCrawler from epoch {}", last_epoch_number);
+                    let epoch_crawler = EpochCrawler::new(node, entry_sender.clone(), interval);
+                    tokio::spawn(async move {
+                        epoch_crawler.run(last_epoch_number).await;
+                    });
+                }
+                "PoolCrawler" => {
+                    let pool_crawler = PoolCrawler::new(rpc_node(), entry_sender.clone(), interval);
+                    tokio::spawn(async move {
+                        pool_crawler.run().await;
+                    });
+                }
+                "CellCrawler" => {
+                    let node = rpc_node();
+                    let last_cell_block_number = last_number(sink.as_ref(), &node.consensus().id, "CellCrawler").await;
+                    log::info!("Start CellCrawler from block {}", last_cell_block_number);
+                    let recent_hashes =
+                        recent_block_hashes(sink.as_ref(), &node.consensus().id, "CellCrawler", last_cell_block_number).await;
+                    let cell_crawler = CellCrawler::new(node, entry_sender.clone(), interval);
+                    tokio::spawn(async move {
+                        cell_crawler.run(last_cell_block_number, recent_hashes).await;
+                    });
+                }
+                "ChainTransactionCrawler" => {
+                    let node = rpc_node();
+                    let last_block_number =
+                        last_number(sink.as_ref(), &node.consensus().id, "ChainTransactionCrawler").await;
+                    log::info!("Start ChainTransactionCrawler from block {}", last_block_number);
+                    let chain_transaction_crawler = ChainTransactionCrawler::new(node, entry_sender.clone(), interval);
+                    tokio::spawn(async move {
+                        chain_transaction_crawler.run(last_block_number).await;
+                    });
+                }
+                "RetentionTransactionCrawler" => {
+                    let retention_transaction_crawler =
+                        RetentionTransactionCrawler::new(rpc_node(), entry_sender.clone(), interval);
+                    tokio::spawn(async move {
+                        retention_transaction_crawler.run().await;
+                    });
+                }
+                "SubscribeNewTransaction" => {
+                    let mut subscriber = SubscribeNewTransaction::new(rpc_node(), entry_sender.clone());
+                    let subscription_addr = subscription_addr();
+                    tokio::spawn(async move {
+                        subscriber.run(subscription_addr).await;
+                    });
+                }
+                "SubscribeProposedTransaction" => {
+                    let mut subscriber = SubscribeProposedTransaction::new(rpc_node(), entry_sender.clone());
+                    let subscription_addr = subscription_addr();
+                    tokio::spawn(async move {
+                        subscriber.run(subscription_addr).await;
+                    });
+                }
+                "SubscribeRejectedTransaction" => {
+                    let mut subscriber = SubscribeRejectedTransaction::new(rpc_node(), entry_sender.clone());
+                    let subscription_addr = subscription_addr();
+                    tokio::spawn(async move {
+                        subscriber.run(subscription_addr).await;
+                    });
+                }
+                "CompactBlockCrawler" => {
+                    let shared = Arc::new(RwLock::new(SharedState::new()));
+                    let compact_block_crawler =
+                        CompactBlockCrawler::new(rpc_node(), entry_sender.clone(), Arc::clone(&shared), enrichment());
+                    // workaround for Rust lifetime
+                    _connectors.push(
+                        ConnectorBuilder::new()
+                            .protocol_metas(compact_block_crawler.build_protocol_metas())
+                            .listening_addresses(vec![])
+                            .build(compact_block_crawler, shared),
+                    );
+                }
+                "NetworkCrawler" => {
                     log::info!("Start listening {:?}", network);
                     let shared = Arc::new(RwLock::new(SharedState::new()));
-                    let network_crawler =
-                        NetworkCrawler::new(network.clone(), query_sender.clone(), Arc::clone(&shared), witness_bound);
+                    let mut network_crawler = NetworkCrawler::new(
+                        network,
+                        entry_sender.clone(),
+                        Arc::clone(&shared),
+                        enrichment(),
+                        prune_window,
+                        dial_concurrency,
+                    );
+                    let listening_addresses = network_config
+                        .listen_address
+                        .iter()
+                        .map(|address| parse_multiaddr("listen_address", address))
+                        .collect::<Vec<_>>();
+                    if !listening_addresses.is_empty() {
+                        let public_address = network_config
+                            .public_address
+                            .as_ref()
+                            .map(|address| parse_multiaddr("public_address", address));
+                        log::info!("NetworkCrawler listens on {:?}, advertises {:?}", listening_addresses, public_address);
+                        network_crawler.listen(public_address);
+                        tokio::spawn(network_crawler.clone().dial_back_inbound_peers(dial_concurrency));
+                    }
+                    tokio::spawn(network_crawler.clone().probe_addresses(dial_concurrency));
                     // workaround for Rust lifetime
                     _connectors.push(
                         ConnectorBuilder::new()
                             .protocol_metas(network_crawler.build_protocol_metas())
-                            .listening_addresses(vec![])
+                            .listening_addresses(listening_addresses)
                             .build(network_crawler, shared),
                     );
                 }
-            }
-            _ => {
-                log::error!("Unknown topic \"{}\"", topic);
-                unreachable!()
+                _ => {
+                    log::error!("Unknown topic \"{}\"", topic);
+                    unreachable!()
+                }
             }
         }
     }
 
-    // loop listen and batch execute queries
+    // loop listen and batch write entries, flush at least every `max_batch_timeout` even when
+    // idle, so the sink retries its backlog
     let max_batch_size: usize = 200;
     let max_batch_timeout = Duration::from_secs(3);
-    let mut batch: Vec<String> = Vec::with_capacity(max_batch_size);
-    let mut last_batch_instant = Instant::now();
-    while let Some(query) = query_receiver.recv().await {
-        log::debug!("new query: {}", query);
-        batch.push(query);
-
-        if batch.len() >= max_batch_size || last_batch_instant.elapsed() >= max_batch_timeout {
-            log::debug!("batch_execute {} queries", batch.len());
-
-            let batch_query: String = batch.join(";");
-            pg.batch_execute(&batch_query).await.unwrap_or_else(|err| {
-                log::error!("batch_execute(\"{}\"), error: {}", batch_query, err)
-            });
-
-            last_batch_instant = Instant::now();
-            batch = Vec::new();
+    let mut batch: Vec<Entry> = Vec::with_capacity(max_batch_size);
+    let mut flush_interval = tokio::time::interval(max_batch_timeout);
+    flush_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            entry = entry_receiver.recv() => match entry {
+                Some(entry) => {
+                    log::debug!("new entry: {:?}", entry);
+                    batch.push(entry);
+                    if batch.len() < max_batch_size {
+                        continue;
+                    }
+                }
+                None => break,
+            },
+            _ = flush_interval.tick() => {}
+            _ = &mut shutdown => break,
         }
+
+        write_batch(sink.as_mut(), &mut batch).await;
     }
+
     log::info!("CKBAnalyzer shutdown");
+    write_batch(sink.as_mut(), &mut batch).await;
+    if let Err(err) = sink.flush(SHUTDOWN_FLUSH_TIMEOUT).await {
+        log::error!("flush sink on shutdown, error: {}", err);
+    }
+}
+
+// Give up writing the sink backlog on shutdown after
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn write_batch(sink: &mut dyn Sink, batch: &mut Vec<Entry>) {
+    log::debug!("write_batch {} entries", batch.len());
+    if let Err(err) = sink.write_batch(batch).await {
+        log::error!("write_batch {} entries, error: {}", batch.len(), err);
+    }
+    batch.clear();
+}
+
+// Command-line `--ckb-network`, `--topics`, `--ckb-rpc-url` and `--ckb-subscription-addr` take
+// precedence over the config file. Exit if the endpoints are given for several networks, or a
+// topic is unknown.
+fn resolve_network_configs(matches: &ArgMatches, config: Option<&CKBAnalyzerConfig>) -> Vec<NetworkConfig> {
+    let topics = values_t_or_exit!(matches, "topics", String);
+    let mut network_configs = match config {
+        Some(config) if matches.occurrences_of("network") == 0 => config.networks.clone(),
+        _ => values_t_or_exit!(matches, "network", String)
+            .into_iter()
+            .map(|name| {
+                let network_type = CKBNetworkType::from(name);
+                config
+                    .and_then(|config| {
+                        config
+                            .networks
+                            .iter()
+                            .find(|network_config| network_config.network_type == network_type)
+                            .cloned()
+                    })
+                    .unwrap_or_else(|| NetworkConfig::new(network_type))
+            })
+            .collect(),
+    };
+
+    // One node serves one network
+    for name in ["ckb-rpc-url", "ckb-subscription-addr"] {
+        if matches.is_present(name) && network_configs.len() != 1 {
+            clap::Error::with_description(
+                &format!(
+                    "\"--{}\" requires exactly one network, but {} are selected; select one with \"--ckb-network\", or configure it per network in the config file",
+                    name,
+                    network_configs.len()
+                ),
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+    }
+
+    for network_config in network_configs.iter_mut() {
+        if matches.occurrences_of("topics") > 0 || network_config.topics.is_none() {
+            network_config.topics = Some(topics.clone());
+        }
+        // Unlike `--topics`, the topics of the config file are not validated by clap
+        for topic in network_config.topics.iter().flatten() {
+            if !TOPICS.contains(&topic.as_str()) {
+                clap::Error::with_description(
+                    &format!(
+                        "unknown topic \"{}\" of network {:?}, expected one of {}",
+                        topic,
+                        network_config.network_type,
+                        TOPICS.join(", ")
+                    ),
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit();
+            }
+        }
+        if let Some(ckb_rpc_url) = matches.value_of("ckb-rpc-url") {
+            network_config.rpc_url = Some(ckb_rpc_url.to_string());
+        } else if network_config.rpc_url.is_none() {
+            network_config.rpc_url = env::var("CKB_RPC_URL").ok();
+        }
+        if let Some(ckb_subscription_addr) = matches.value_of("ckb-subscription-addr") {
+            network_config.subscription_addr = Some(ckb_subscription_addr.to_string());
+        } else if network_config.subscription_addr.is_none() {
+            network_config.subscription_addr = env::var("CKB_SUBSCRIPTION_ADDR").ok();
+        }
+    }
+    network_configs
+}
+
+async fn last_number(sink: &dyn Sink, ckb_network_name: &str, topic: &str) -> u64 {
+    sink.last_number(ckb_network_name, topic)
+        .await
+        .unwrap_or_else(|err| panic!("query last number of {} on {}, error: {}", topic, ckb_network_name, err))
+}
+
+// Hashes of the blocks stored by `topic` up to `number`, to detect the reorgs happened while
+// stopped
+async fn recent_block_hashes(sink: &dyn Sink, ckb_network_name: &str, topic: &str, number: u64) -> Vec<(u64, Byte32)> {
+    sink.recent_block_hashes(ckb_network_name, topic, number, REORG_DETECTION_WINDOW)
+        .await
+        .unwrap_or_else(|err| panic!("query recent block hashes of {} on {}, error: {}", topic, ckb_network_name, err))
+}
+
+fn parse_multiaddr(name: &str, address: &str) -> p2p::multiaddr::Multiaddr {
+    address
+        .parse()
+        .unwrap_or_else(|err| panic!("invalid {} \"{}\", error: {:?}", name, address, err))
+}
+
+// Number of snapshot entries per batch written into the sink
+const SNAPSHOT_CHUNK_SIZE: usize = 1000;
+
+// Crawl `network` breadth-first from its bootnodes until the frontier is exhausted or
+// `deadline` passes, then write the snapshot and its geographic distribution into `sink`
+async fn take_snapshot(
+    sink: &mut dyn Sink,
+    network: CKBNetworkType,
+    geo_resolver: &Arc<dyn GeoResolver>,
+    dial_concurrency: usize,
+    deadline: Duration,
+) {
+    let started_at = chrono::Utc::now().naive_utc();
+    let snapshot_id = started_at.timestamp_millis();
+    log::info!("Start snapshot {} of {:?}, deadline: {:?}", snapshot_id, network, deadline);
+
+    // Snapshot mode sends no entries, the channel just satisfies the crawler
+    let (entry_sender, _entry_receiver) = crossbeam::channel::unbounded();
+    let shared = Arc::new(RwLock::new(SharedState::new()));
+    let network_crawler = NetworkCrawler::new_snapshot(network, entry_sender, Arc::clone(&shared), dial_concurrency);
+    let _connector = ConnectorBuilder::new()
+        .protocol_metas(network_crawler.build_protocol_metas())
+        .listening_addresses(vec![])
+        .build(network_crawler.clone(), shared);
+
+    let deadline_at = Instant::now() + deadline;
+    let complete = loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        if network_crawler.is_exhausted() {
+            break true;
+        }
+        if Instant::now() >= deadline_at {
+            log::warn!("Snapshot {} of {:?} reached the deadline", snapshot_id, network);
+            break false;
+        }
+    };
+
+    let (peers, edges) = network_crawler.snapshot_entries(snapshot_id);
+    // Rate-limited like the enrichment worker, ipinfo.io may be the fallback
+    let ips = peers.iter().map(|peer| peer.ip.clone()).collect();
+    let located = enrichment::geolocate(Arc::clone(geo_resolver), ips).await;
+    let cities = geo::geo_cities(&peers, &located);
+    let snapshot = entry::Snapshot {
+        network: network.into_legacy_str(),
+        id: snapshot_id,
+        started_at,
+        finished_at: chrono::Utc::now().naive_utc(),
+        complete,
+        n_peers: peers.len() as i32,
+        n_edges: edges.len() as i32,
+    };
+    log::info!("Finish snapshot {:?}", snapshot);
+    let entries = peers
+        .into_iter()
+        .map(Entry::from)
+        .chain(edges.into_iter().map(Entry::from))
+        .chain(cities.into_iter().map(Entry::from))
+        .chain(std::iter::once(snapshot.into()))
+        .collect::<Vec<_>>();
+    for chunk in entries.chunks(SNAPSHOT_CHUNK_SIZE) {
+        sink.write_batch(chunk)
+            .await
+            .unwrap_or_else(|err| panic!("write snapshot {}, error: {}", snapshot_id, err));
+    }
+    sink.flush(SHUTDOWN_FLUSH_TIMEOUT)
+        .await
+        .unwrap_or_else(|err| panic!("write snapshot {}, error: {}", snapshot_id, err));
+}
+
+// Use env var if no `[db]` config presents
+fn postgres_config(db: Option<DBConfig>) -> tokio_postgres::Config {
+    let db = db.unwrap_or_else(|| {
+        let host = env::var_os("PGHOST")
+            .or_else(|| env::var_os("POSTGRES_HOST"))
+            .expect("requires environment variable \"PGHOST\" or \"POSTGRES_HOST\"")
+            .to_string_lossy()
+            .to_string();
+        let port = env::var_os("PGPORT")
+            .or_else(|| env::var_os("POSTGRES_PORT"))
+            .expect("requires environment variable \"PGPORT\" or \"POSTGRES_PORT\"")
+            .to_string_lossy()
+            .parse::<u16>()
+            .expect("requires environment variable \"PGPORT\" or \"POSTGRES_PORT\" to be a number");
+        let database = env::var_os("PGDATABASE")
+            .or_else(|| env::var_os("POSTGRES_DB"))
+            .expect("requires environment variable \"PGDATABASE\" or \"POSTGRES_DB\"")
+            .to_string_lossy()
+            .to_string();
+        let user = env::var_os("PGUSER")
+            .or_else(|| env::var_os("POSTGRES_USER"))
+            .expect("requires environment variable \"PGUSER\" or \"POSTGRES_USER\"")
+            .to_string_lossy()
+            .to_string();
+        let password = env::var_os("PGPASSWORD")
+            .or_else(|| env::var_os("POSTGRES_PASSWORD"))
+            .expect("requires environment variable \"PGPASSWORD\" or \"POSTGRES_PASSWORD\"")
+            .to_string_lossy()
+            .to_string();
+        DBConfig::new(host, port, database, user, password)
+    });
+
+    let mut config = tokio_postgres::Config::new();
+    config
+        .host(&db.host)
+        .port(db.port)
+        .dbname(&db.database)
+        .user(&db.user)
+        .password(&db.password)
+        .application_name("CKBAnalyzer");
+    config
 }
 
 fn init_logger() -> ckb_logger_service::LoggerInitGuard {
@@ -183,6 +549,21 @@ fn init_logger() -> ckb_logger_service::LoggerInitGuard {
         .unwrap_or_else(|err| panic!("failed to init the logger service, error: {}", err))
 }
 
+// Topics accepted by `--topics` and `[networks.<name>].topics`
+const TOPICS: &[&str] = &[
+    "ChainCrawler",
+    "EpochCrawler",
+    "PoolCrawler",
+    "CellCrawler",
+    "ChainTransactionCrawler",
+    "RetentionTransactionCrawler",
+    "CompactBlockCrawler",
+    "SubscribeNewTransaction",
+    "SubscribeProposedTransaction",
+    "SubscribeRejectedTransaction",
+    "NetworkCrawler",
+];
+
 pub fn clap_app() -> App<'static, 'static> {
     App::new("ckb-analyzer")
         .version(crate_version!())
@@ -214,6 +595,22 @@ pub fn clap_app() -> App<'static, 'static> {
                     "ckb_testnet"
                 ]),
         )
+        .arg(
+            Arg::with_name("ckb-rpc-url")
+                .long("ckb-rpc-url")
+                .value_name("URL")
+                .required(false)
+                .takes_value(true)
+                .help("CKB node RPC endpoint, required by RPC-driven topics"),
+        )
+        .arg(
+            Arg::with_name("ckb-subscription-addr")
+                .long("ckb-subscription-addr")
+                .value_name("HOSTPORT")
+                .required(false)
+                .takes_value(true)
+                .help("CKB node TCP subscription address, required by Subscribe* topics"),
+        )
         .arg(
             Arg::with_name("topics")
                 .long("topics")
@@ -225,12 +622,31 @@ pub fn clap_app() -> App<'static, 'static> {
                 .default_value(
                     "NetworkCrawler",
                 )
-                .possible_values(&[
-                    "ChainCrawler",
-                    "EpochCrawler",
-                    "PoolCrawler",
-                    "CellCrawler",
-                    "NetworkCrawler",
-                ]),
+                .possible_values(TOPICS),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Create or migrate the schemas of the networks in Postgres, then exit"),
+        )
+        .subcommand(
+            SubCommand::with_name("snapshot")
+                .about("Crawl the p2p networks from their bootnodes once, write the snapshots, then exit")
+                .arg(
+                    Arg::with_name("deadline")
+                        .long("deadline")
+                        .value_name("SECONDS")
+                        .default_value("3600")
+                        .help("Stop crawling after this long even if the frontier is not exhausted"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Write the entries of a dead letter file into the sink, then exit")
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .required(true)
+                        .index(1),
+                ),
         )
 }
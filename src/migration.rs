@@ -0,0 +1,180 @@
+//! Versioned migrations of the per-network schemas, embedded into the binary.
+//!
+//! Each migration is a SQL script under `sql/migrations`, in which `{schema}` is replaced with
+//! the schema name of the network, e.g. `ckb`, `ckb_testnet` or `ckb_dev`. The applied versions
+//! are recorded in `<schema>.schema_migrations`.
+//!
+//! To change the schema, append a script and its entry in `MIGRATIONS`; never edit an applied
+//! one.
+
+use tokio_postgres::{Client, Error, GenericClient};
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: include_str!("../sql/migrations/0001_init.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "reorg",
+        sql: include_str!("../sql/migrations/0002_reorg.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "checkpoint",
+        sql: include_str!("../sql/migrations/0003_checkpoint.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "peer_session_history",
+        sql: include_str!("../sql/migrations/0004_peer_session_history.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "peer_edge",
+        sql: include_str!("../sql/migrations/0005_peer_edge.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "crawl_round",
+        sql: include_str!("../sql/migrations/0006_crawl_round.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "snapshot",
+        sql: include_str!("../sql/migrations/0007_snapshot.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "peer_identify",
+        sql: include_str!("../sql/migrations/0008_peer_identify.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "peer_id",
+        sql: include_str!("../sql/migrations/0009_peer_id.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "transport_reachability",
+        sql: include_str!("../sql/migrations/0010_transport_reachability.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "address_probe",
+        sql: include_str!("../sql/migrations/0011_address_probe.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "peer_latency",
+        sql: include_str!("../sql/migrations/0012_peer_latency.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "inbound_peer",
+        sql: include_str!("../sql/migrations/0013_inbound_peer.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "ipinfo_asn",
+        sql: include_str!("../sql/migrations/0014_ipinfo_asn.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "geo_city",
+        sql: include_str!("../sql/migrations/0015_geo_city.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "block_propagation",
+        sql: include_str!("../sql/migrations/0016_block_propagation.sql"),
+    },
+];
+
+/// Return the migrations not applied to `schema` yet
+pub async fn pending(client: &Client, schema: &str) -> Result<Vec<&'static Migration>, Error> {
+    let exists: bool = client
+        .query_one(
+            "SELECT to_regclass($1) IS NOT NULL",
+            &[&format!("{}.schema_migrations", schema)],
+        )
+        .await?
+        .get(0);
+    let current = if exists {
+        current_version(client, schema).await?
+    } else {
+        0
+    };
+    Ok(MIGRATIONS.iter().filter(|migration| migration.version > current).collect())
+}
+
+/// Create `schema` if absent and apply the pending migrations in one transaction, return the
+/// applied ones
+pub async fn migrate(client: &mut Client, schema: &str) -> Result<Vec<&'static Migration>, Error> {
+    let transaction = client.transaction().await?;
+    // The lock serializes the analyzers migrating the same schema concurrently
+    transaction
+        .batch_execute(&format!(
+            "CREATE SCHEMA IF NOT EXISTS {schema}; \
+            CREATE TABLE IF NOT EXISTS {schema}.schema_migrations (\
+                version     INT             PRIMARY KEY, \
+                name        TEXT            NOT NULL, \
+                applied_at  TIMESTAMPTZ     NOT NULL DEFAULT now()\
+            ); \
+            LOCK TABLE {schema}.schema_migrations IN EXCLUSIVE MODE;",
+            schema = schema
+        ))
+        .await?;
+
+    let current = current_version(&transaction, schema).await?;
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+        log::info!(
+            "Applying migration {:04}_{} to schema {}",
+            migration.version,
+            migration.name,
+            schema
+        );
+        transaction
+            .batch_execute(&migration.sql.replace("{schema}", schema))
+            .await?;
+        transaction
+            .execute(
+                format!(
+                    "INSERT INTO {}.schema_migrations(version, name) VALUES ($1, $2)",
+                    schema
+                )
+                .as_str(),
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        applied.push(migration);
+    }
+    transaction.commit().await?;
+    Ok(applied)
+}
+
+async fn current_version<C: GenericClient>(client: &C, schema: &str) -> Result<i32, Error> {
+    let row = client
+        .query_one(
+            format!("SELECT COALESCE(MAX(version), 0) FROM {}.schema_migrations", schema).as_str(),
+            &[],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+#[test]
+fn test_migrations_are_ordered() {
+    for (i, migration) in MIGRATIONS.iter().enumerate() {
+        assert_eq!(migration.version, i as i32 + 1);
+        assert!(migration.sql.contains("{schema}."));
+    }
+}
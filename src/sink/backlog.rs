@@ -0,0 +1,256 @@
+use crate::entry::Entry;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Backlog buffers the entries that a sink cannot write for the moment, in arrival order.
+///
+/// Up to `capacity` entries are kept in memory. Beyond that, entries are appended to the spill
+/// file if configured, otherwise they are dropped. Once something is spilled, the newer entries
+/// go to the spill file as well, so that popping keeps the arrival order. Entries left in the
+/// spill file by a previous run are picked up too.
+///
+/// Spilled entries stay in the spill file until they are acknowledged as written, see `ack`, so
+/// a crash never loses them.
+pub struct Backlog {
+    capacity: usize,
+    memory: VecDeque<Entry>,
+    spill_path: Option<PathBuf>,
+    // Number of entries in the spill file not loaded yet
+    spilled: usize,
+    // Number of entries at the head of the spill file already loaded, the file is truncated once
+    // they are all written
+    loaded: usize,
+    // Number of entries at the front of `memory` loaded from the spill file, an upper bound
+    memory_loaded: usize,
+}
+
+impl Backlog {
+    pub fn new(capacity: usize, spill_path: Option<PathBuf>) -> Self {
+        let spilled = spill_path
+            .as_ref()
+            .and_then(|path| File::open(path).ok())
+            .map(|file| BufReader::new(file).lines().count())
+            .unwrap_or(0);
+        if spilled > 0 {
+            log::info!(
+                "backlog resumes {} spilled entries from {}",
+                spilled,
+                spill_path.as_ref().unwrap().display()
+            );
+        }
+        Self {
+            capacity,
+            memory: VecDeque::new(),
+            spill_path,
+            spilled,
+            loaded: 0,
+            memory_loaded: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.spilled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append `entries` to the back
+    pub fn push(&mut self, entries: Vec<Entry>) -> std::io::Result<()> {
+        let mut spill = Vec::new();
+        let mut dropped = 0;
+        for entry in entries {
+            if self.spilled == 0 && spill.is_empty() && self.memory.len() < self.capacity {
+                self.memory.push_back(entry);
+            } else if self.spill_path.is_some() {
+                spill.push(entry);
+            } else {
+                dropped += 1;
+            }
+        }
+        if dropped > 0 {
+            log::error!("backlog is full, drop {} entries", dropped);
+        }
+        if !spill.is_empty() {
+            self.spill(&spill)?;
+        }
+        Ok(())
+    }
+
+    /// Put back `entries` that were just popped, in front of the others
+    pub fn push_front(&mut self, entries: Vec<Entry>) {
+        // Any of them may come from the spill file, keep it until they are popped again
+        self.memory_loaded = (self.memory_loaded + entries.len()).min(self.loaded);
+        for entry in entries.into_iter().rev() {
+            self.memory.push_front(entry);
+        }
+    }
+
+    /// Pop at most `n` entries from the front. Call `ack` once they are written, or
+    /// `push_front` to put back the unwritten ones.
+    pub fn pop(&mut self, n: usize) -> std::io::Result<Option<Vec<Entry>>> {
+        if self.memory.is_empty() && self.spilled > 0 {
+            self.unspill()?;
+        }
+        if self.memory.is_empty() {
+            return Ok(None);
+        }
+        let n = n.min(self.memory.len());
+        self.memory_loaded = self.memory_loaded.saturating_sub(n);
+        Ok(Some(self.memory.drain(..n).collect()))
+    }
+
+    /// Acknowledge that the entries popped last were written. Once every entry loaded from the
+    /// spill file is written, remove them from the file.
+    pub fn ack(&mut self) -> std::io::Result<()> {
+        if self.memory_loaded > 0 || self.loaded == 0 {
+            return Ok(());
+        }
+        let path = self.spill_path.as_ref().expect("checked");
+        if self.spilled == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            let rest_path = path.with_extension("rest");
+            {
+                let reader = BufReader::new(File::open(path)?);
+                let mut rest = BufWriter::new(File::create(&rest_path)?);
+                for line in reader.lines().skip(self.loaded) {
+                    rest.write_all(line?.as_bytes())?;
+                    rest.write_all(b"\n")?;
+                }
+                rest.flush()?;
+            }
+            std::fs::rename(&rest_path, path)?;
+        }
+        self.loaded = 0;
+        Ok(())
+    }
+
+    fn spill(&mut self, entries: &[Entry]) -> std::io::Result<()> {
+        let path = self.spill_path.as_ref().expect("checked");
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = BufWriter::new(file);
+        for entry in entries {
+            serde_json::to_writer(&mut writer, entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        self.spilled += entries.len();
+        log::warn!("spill {} entries into {}", entries.len(), path.display());
+        Ok(())
+    }
+
+    // Load the next `capacity` spilled entries into memory, leaving the spill file as is until
+    // they are acknowledged
+    fn unspill(&mut self) -> std::io::Result<()> {
+        let path = self.spill_path.as_ref().expect("checked");
+        let reader = BufReader::new(File::open(path)?);
+        let mut n = 0;
+        for line in reader.lines().skip(self.loaded).take(self.capacity) {
+            self.memory.push_back(serde_json::from_str(&line?)?);
+            n += 1;
+        }
+        self.loaded += n;
+        self.memory_loaded += n;
+        self.spilled = self.spilled.saturating_sub(n);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_backlog_keeps_order_across_spill() {
+    use crate::entry;
+
+    let time = chrono::NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap();
+    let entries = |range: std::ops::Range<u64>| -> Vec<Entry> {
+        range
+            .map(|block_number| {
+                entry::CompactBlockFirstSeen {
+                    network: "ckb".to_string(),
+                    time,
+                    block_number,
+                    ip: "127.0.0.1".to_string(),
+                }
+                .into()
+            })
+            .collect()
+    };
+    let block_numbers = |entries: Vec<Entry>| -> Vec<u64> {
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::CompactBlockFirstSeen(entry) => entry.block_number,
+                _ => unreachable!(),
+            })
+            .collect()
+    };
+
+    let path = std::env::temp_dir().join(format!("ckb-analyzer-backlog-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut backlog = Backlog::new(3, Some(path.clone()));
+    backlog.push(entries(0..5)).unwrap();
+    backlog.push(entries(5..7)).unwrap();
+    assert_eq!(backlog.len(), 7);
+
+    let popped = backlog.pop(2).unwrap().unwrap();
+    assert_eq!(block_numbers(popped.clone()), vec![0, 1]);
+    backlog.push_front(popped);
+    assert_eq!(block_numbers(backlog.pop(10).unwrap().unwrap()), vec![0, 1, 2]);
+    backlog.ack().unwrap();
+    let popped = backlog.pop(1).unwrap().unwrap();
+    assert_eq!(block_numbers(popped.clone()), vec![3]);
+    backlog.push_front(popped);
+    assert_eq!(block_numbers(backlog.pop(10).unwrap().unwrap()), vec![3, 4, 5]);
+    // Not acknowledged, a restart picks them up again
+    assert_eq!(Backlog::new(3, Some(path.clone())).len(), 4);
+    backlog.ack().unwrap();
+    assert_eq!(Backlog::new(3, Some(path.clone())).len(), 1);
+    assert_eq!(block_numbers(backlog.pop(10).unwrap().unwrap()), vec![6]);
+    backlog.ack().unwrap();
+    assert!(backlog.pop(10).unwrap().is_none());
+    assert!(backlog.is_empty());
+    assert!(!path.exists());
+
+    let mut backlog = Backlog::new(2, None);
+    backlog.push(entries(0..3)).unwrap();
+    assert_eq!(backlog.len(), 2);
+}
+
+#[test]
+fn test_backlog_spills_batches_whole() {
+    use crate::entry;
+
+    let time = chrono::NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap();
+    let checkpoint = |number: u64| -> Entry {
+        entry::Checkpoint {
+            network: "ckb".to_string(),
+            time,
+            topic: "CellCrawler".to_string(),
+            number,
+        }
+        .into()
+    };
+
+    let path = std::env::temp_dir().join(format!("ckb-analyzer-backlog-batch-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut backlog = Backlog::new(1, Some(path.clone()));
+    let batch = Entry::Batch {
+        records: vec![checkpoint(1), checkpoint(2)],
+        backfill: true,
+    };
+    backlog.push(vec![checkpoint(0), batch]).unwrap();
+    assert_eq!(backlog.len(), 2);
+    assert_eq!(backlog.pop(10).unwrap().unwrap().len(), 1);
+    match &backlog.pop(10).unwrap().unwrap()[..] {
+        [Entry::Batch {
+            records,
+            backfill: true,
+        }] => assert_eq!(records.len(), 2),
+        popped => panic!("unexpected {:?}", popped),
+    }
+    backlog.ack().unwrap();
+    assert!(!path.exists());
+}
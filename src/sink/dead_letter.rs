@@ -0,0 +1,91 @@
+use crate::entry::Entry;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Record of an entry that a sink failed to persist permanently
+#[derive(Serialize, Deserialize)]
+pub struct DeadLetterRecord {
+    pub time: chrono::NaiveDateTime,
+    pub error: String,
+    pub entry: Entry,
+}
+
+/// DeadLetter appends the permanently failing entries to a file, one JSON record per line, so
+/// they can be fixed and replayed by `ckb-analyzer replay`.
+///
+/// The file is only created once the first record is written.
+pub struct DeadLetter {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl DeadLetter {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, writer: None }
+    }
+
+    pub fn write(&mut self, entry: &Entry, error: &str) -> std::io::Result<()> {
+        log::error!(
+            "write {:?} into dead letter {}, error: {}",
+            entry,
+            self.path.display(),
+            error
+        );
+        if self.writer.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.writer = Some(BufWriter::new(file));
+        }
+        let writer = self.writer.as_mut().unwrap();
+        let record = DeadLetterRecord {
+            time: chrono::Utc::now().naive_utc(),
+            error: error.to_string(),
+            entry: entry.clone(),
+        };
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+}
+
+/// Read the entries of a dead letter file
+pub fn read(path: &Path) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: DeadLetterRecord = serde_json::from_str(&line)?;
+        entries.push(record.entry);
+    }
+    Ok(entries)
+}
+
+#[test]
+fn test_dead_letter_round_trip() {
+    use crate::entry;
+
+    let path = std::env::temp_dir().join(format!("ckb-analyzer-dead-letter-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let entry: Entry = entry::Epoch {
+        network: "ckb".to_string(),
+        start_time: chrono::NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap(),
+        end_time: chrono::NaiveDateTime::from_timestamp_opt(1_600_014_400, 0).unwrap(),
+        number: 1,
+        length: 1800,
+        start_number: 1000,
+        n_uncles: 0,
+        difficulty: "0x1".to_string(),
+    }
+    .into();
+
+    let mut dead_letter = DeadLetter::new(path.clone());
+    dead_letter.write(&entry, "duplicate key").unwrap();
+    dead_letter.write(&entry, "duplicate key").unwrap();
+    let entries = read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(format!("{:?}", entries[0]), format!("{:?}", entry));
+}
@@ -0,0 +1,81 @@
+use crate::entry::Entry;
+use crate::sink::{Sink, SinkError};
+use async_trait::async_trait;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// JsonLinesSink appends entries to a file, one JSON object per line.
+pub struct JsonLinesSink {
+    writer: BufWriter<File>,
+}
+
+impl JsonLinesSink {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for JsonLinesSink {
+    async fn write_batch(&mut self, entries: &[Entry]) -> Result<(), SinkError> {
+        for entry in entries.iter().flat_map(Entry::records) {
+            serde_json::to_writer(&mut self.writer, entry)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_json_lines_round_trip() {
+    use crate::ckb_types::{
+        bytes::Bytes,
+        core::ScriptHashType,
+        packed::{Byte32, OutPoint},
+    };
+    use crate::entry;
+
+    let time = chrono::NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap();
+    let entries: Vec<Entry> = vec![
+        entry::CreatedCell {
+            network: "ckb".to_string(),
+            time,
+            block_number: 1,
+            block_hash: Byte32::zero(),
+            tx_index: 0,
+            out_point: OutPoint::new(Byte32::zero(), 1),
+            lock_hash_type: ScriptHashType::Type,
+            lock_code_hash: Byte32::zero(),
+            lock_args: Some(Bytes::from(vec![0x12, 0x34])),
+            type_hash_type: None,
+            type_code_hash: None,
+        }
+        .into(),
+        entry::SpentCell {
+            network: "ckb".to_string(),
+            time,
+            block_number: 2,
+            out_point: OutPoint::new(Byte32::zero(), 1),
+        }
+        .into(),
+    ];
+
+    let path = std::env::temp_dir().join(format!("ckb-analyzer-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut sink = JsonLinesSink::open(&path).unwrap();
+    sink.write_batch(&entries).await.unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let decoded = content
+        .lines()
+        .map(|line| serde_json::from_str::<Entry>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(format!("{:?}", decoded), format!("{:?}", entries));
+    assert!(content.starts_with("{\"kind\":\"CreatedCell\""));
+}
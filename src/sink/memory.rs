@@ -0,0 +1,73 @@
+use crate::entry::Entry;
+use crate::sink::{Sink, SinkError};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// MemorySink keeps the latest `capacity` entries in memory and drops the older ones.
+///
+/// Clones share the same buffer, keep one to inspect what was written.
+#[derive(Clone)]
+pub struct MemorySink {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<Entry>>>,
+}
+
+impl MemorySink {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Default::default(),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[async_trait]
+impl Sink for MemorySink {
+    async fn write_batch(&mut self, entries: &[Entry]) -> Result<(), SinkError> {
+        let mut buffer = self.entries.lock().unwrap();
+        for entry in entries.iter().flat_map(Entry::records) {
+            if buffer.len() >= self.capacity {
+                buffer.pop_front();
+            }
+            buffer.push_back(entry.clone());
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_memory_sink_keeps_latest_entries() {
+    use crate::entry;
+
+    let time = chrono::NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap();
+    let entries: Vec<Entry> = (0..3)
+        .map(|block_number| {
+            entry::CompactBlockFirstSeen {
+                network: "ckb".to_string(),
+                time,
+                block_number,
+                ip: "127.0.0.1".to_string(),
+            }
+            .into()
+        })
+        .collect();
+
+    let sink = MemorySink::new(2);
+    let mut writer: Box<dyn Sink> = Box::new(sink.clone());
+    writer.write_batch(&entries).await.unwrap();
+
+    let block_numbers = sink
+        .entries()
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::CompactBlockFirstSeen(entry) => entry.block_number,
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(block_numbers, vec![1, 2]);
+}
@@ -0,0 +1,88 @@
+//! Sinks consume the entries emitted by crawlers.
+//!
+//! `PostgresSink` is the production backend. `JsonLinesSink` appends one JSON object per entry
+//! to a file, so the analyzer can run without a database. `MemorySink` keeps the latest entries
+//! in memory, so tests can assert on what crawlers emitted.
+
+use crate::ckb_types::packed::Byte32;
+use crate::config::SinkConfig;
+use crate::entry::Entry;
+use async_trait::async_trait;
+use backlog::Backlog;
+use std::time::Duration;
+
+mod backlog;
+pub mod dead_letter;
+mod json_lines;
+mod memory;
+mod postgres;
+
+pub use json_lines::JsonLinesSink;
+pub use memory::MemorySink;
+pub use postgres::PostgresSink;
+
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+#[async_trait]
+pub trait Sink: Send {
+    /// Persist a batch of entries
+    async fn write_batch(&mut self, entries: &[Entry]) -> Result<(), SinkError>;
+
+    /// Write the entries buffered for a later retry, waiting at most `timeout`, e.g. on shutdown.
+    /// Sinks that write straight away have nothing to do.
+    async fn flush(&mut self, _timeout: Duration) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Return the last persisted number of `topic` on `network`, the crawler resumes from it.
+    /// Sinks that cannot be queried start over from 0.
+    async fn last_number(&self, _network: &str, _topic: &str) -> Result<u64, SinkError> {
+        Ok(0)
+    }
+
+    /// Return the (number, hash) of the last `limit` blocks of `network` persisted by `topic` up
+    /// to `number`, in ascending order. Crawlers seed their reorg detection with them on
+    /// restart. Sinks that cannot be queried return none.
+    async fn recent_block_hashes(
+        &self,
+        _network: &str,
+        _topic: &str,
+        _number: u64,
+        _limit: usize,
+    ) -> Result<Vec<(u64, Byte32)>, SinkError> {
+        Ok(Vec::new())
+    }
+
+    /// Check that the storage of `network` is up to date. With `apply`, run the pending
+    /// migrations, otherwise fail if any is pending. Schemaless sinks have nothing to do.
+    async fn migrate(&mut self, _network: &str, _apply: bool) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Open the sink described by `config`. `pg_config` is only evaluated by the Postgres sink.
+pub async fn open(
+    config: &SinkConfig,
+    pg_config: impl FnOnce() -> tokio_postgres::Config,
+) -> Result<Box<dyn Sink>, SinkError> {
+    match config {
+        SinkConfig::Postgres {
+            dead_letter_path,
+            backlog_capacity,
+            spill_path,
+            ..
+        } => Ok(Box::new(
+            PostgresSink::connect(
+                pg_config(),
+                dead_letter_path.clone(),
+                Backlog::new(*backlog_capacity, spill_path.clone()),
+            )
+            .await?,
+        )),
+        SinkConfig::JsonLines { path } => {
+            log::info!("Writing entries into {}", path.display());
+            Ok(Box::new(JsonLinesSink::open(path)?))
+        }
+        SinkConfig::Memory { capacity } => Ok(Box::new(MemorySink::new(*capacity))),
+    }
+}
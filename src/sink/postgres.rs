@@ -0,0 +1,1185 @@
+use crate::ckb_types::{packed::Byte32, prelude::*, H256};
+use crate::entry::Entry;
+use crate::migration;
+use crate::sink::backlog::Backlog;
+use crate::sink::dead_letter::DeadLetter;
+use crate::sink::{Sink, SinkError};
+use async_trait::async_trait;
+use std::cmp::{max, min};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error as _;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{Client, Error, Statement, Transaction};
+
+type Params = Vec<Box<dyn ToSql + Sync + Send>>;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Number of backlog entries written per transaction once reconnected
+const BACKLOG_CHUNK_SIZE: usize = 1000;
+
+/// Tables bulk loaded with COPY when crawlers backfill.
+///
+/// The columns are listed in the same order as the parameters of `insert_statement`, so a
+/// row is encoded from the same parameters as its insertion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum CopyTable {
+    CreatedCell,
+    SpentCell,
+    BlockTransaction,
+}
+
+impl CopyTable {
+    /// Return the schema and table of `entry`, or `None` if it is not copyable
+    fn of(entry: &Entry) -> Option<(&str, Self)> {
+        match entry {
+            Entry::CreatedCell(entry) => Some((&entry.network, CopyTable::CreatedCell)),
+            Entry::SpentCell(entry) => Some((&entry.network, CopyTable::SpentCell)),
+            Entry::BlockTransaction(entry) => Some((&entry.network, CopyTable::BlockTransaction)),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CopyTable::CreatedCell => "created_cell",
+            CopyTable::SpentCell => "spent_cell",
+            CopyTable::BlockTransaction => "block_transaction",
+        }
+    }
+
+    fn columns(&self) -> &'static str {
+        match self {
+            CopyTable::CreatedCell => "time, block_number, tx_index, tx_hash, index, lock_hash_type, lock_code_hash, lock_args, type_hash_type, type_code_hash, block_hash",
+            CopyTable::SpentCell => "time, block_number, tx_hash, index",
+            CopyTable::BlockTransaction => "time, number, size, n_inputs, n_outputs, n_header_deps, n_cell_deps, total_data_size, proposal_id, hash",
+        }
+    }
+
+    fn types(&self) -> &'static [Type] {
+        match self {
+            CopyTable::CreatedCell => &[
+                Type::TIMESTAMP,
+                Type::INT8,
+                Type::INT4,
+                Type::VARCHAR,
+                Type::INT4,
+                Type::INT4,
+                Type::VARCHAR,
+                Type::VARCHAR,
+                Type::INT4,
+                Type::VARCHAR,
+                Type::VARCHAR,
+            ],
+            CopyTable::SpentCell => &[Type::TIMESTAMP, Type::INT8, Type::VARCHAR, Type::INT8],
+            CopyTable::BlockTransaction => &[
+                Type::TIMESTAMP,
+                Type::INT8,
+                Type::INT8,
+                Type::INT4,
+                Type::INT4,
+                Type::INT4,
+                Type::INT4,
+                Type::INT8,
+                Type::VARCHAR,
+                Type::VARCHAR,
+            ],
+        }
+    }
+}
+
+/// PostgresSink turns entries into prepared, parameterized statements.
+///
+/// The schema name is derived from the network of an entry, which never comes from peers;
+/// every other value is sent as a statement parameter.
+///
+/// A batch is inserted in one transaction. Errors other than transient ones bisect the batch
+/// until the offending entries are isolated and sent to the dead letter, so one bad row never
+/// drops its neighbours.
+///
+/// On transient errors, or when the connection dies, entries are kept in the backlog and retried
+/// with exponential backoff, then flushed in order before the newer ones. Writes never wait for
+/// the backoff, so the entry channel keeps draining meanwhile.
+pub struct PostgresSink {
+    config: tokio_postgres::Config,
+    client: Client,
+    dead_letter: DeadLetter,
+    backlog: Backlog,
+
+    // Do not try to write, or reconnect, before
+    retry_at: Instant,
+    retry_backoff: Duration,
+
+    // #{ sql => prepared statement }, scoped to the current connection
+    statements: HashMap<String, Statement>,
+}
+
+impl PostgresSink {
+    pub async fn connect(
+        config: tokio_postgres::Config,
+        dead_letter_path: PathBuf,
+        backlog: Backlog,
+    ) -> Result<Self, Error> {
+        let client = connect_client(&config).await?;
+        Ok(Self {
+            config,
+            client,
+            dead_letter: DeadLetter::new(dead_letter_path),
+            backlog,
+            retry_at: Instant::now(),
+            retry_backoff: INITIAL_BACKOFF,
+            statements: Default::default(),
+        })
+    }
+
+    /// Return whether the client is alive, reconnect if it is dead
+    async fn ensure_connected(&mut self) -> bool {
+        if !self.client.is_closed() {
+            return true;
+        }
+        match connect_client(&self.config).await {
+            Ok(client) => {
+                log::info!("Reconnected to Postgres, {} entries in backlog", self.backlog.len());
+                self.client = client;
+                self.statements.clear();
+                true
+            }
+            Err(err) => {
+                log::error!("reconnect to Postgres, error: {}", err);
+                false
+            }
+        }
+    }
+
+    /// Do not write again before the backoff elapses, and double it
+    fn back_off(&mut self) {
+        log::warn!(
+            "Postgres write failed, retry in {:?}, {} entries in backlog",
+            self.retry_backoff,
+            self.backlog.len()
+        );
+        self.retry_at = Instant::now() + self.retry_backoff;
+        self.retry_backoff = min(self.retry_backoff * 2, MAX_BACKOFF);
+    }
+
+    /// Bulk load the backfill batches with COPY, then insert the other entries. Return the
+    /// entries left unwritten because of transient errors.
+    async fn write_entries(&mut self, entries: &[Entry]) -> Result<Vec<Entry>, SinkError> {
+        let mut inserts = Vec::with_capacity(entries.len());
+        let mut backfills = Vec::new();
+        // Copies run ahead of the insertions, keep everything in order around rollbacks
+        let copyable = !entries.iter().any(|entry| matches!(entry, Entry::Rollback(_)));
+        for entry in entries {
+            match entry {
+                Entry::Batch { backfill: true, .. } if copyable => backfills.push(entry),
+                _ => inserts.push(entry),
+            }
+        }
+
+        if !backfills.is_empty() {
+            match self.copy(&backfills).await {
+                Ok(n) => log::debug!("copy {} rows of {} batches", n, backfills.len()),
+                Err(err) => {
+                    log::error!(
+                        "copy {} batches, error: {}, fallback to insertions",
+                        backfills.len(),
+                        err
+                    );
+                    inserts.splice(0..0, backfills);
+                }
+            }
+        }
+
+        let unwritten = self.insert(&inserts).await?;
+        Ok(unwritten.into_iter().cloned().collect())
+    }
+
+    async fn prepare(&mut self, sql: String) -> Result<Statement, Error> {
+        if let Some(statement) = self.statements.get(&sql) {
+            return Ok(statement.clone());
+        }
+        let statement = self.client.prepare(&sql).await?;
+        self.statements.insert(sql, statement.clone());
+        Ok(statement)
+    }
+
+    /// Insert `entries`, bisecting the failed chunks and sending the single failed entries to the
+    /// dead letter. A batch is never split across transactions: once it fails alone, see
+    /// `insert_isolating`. Once a chunk keeps failing with transient errors, stop and return the
+    /// entries not inserted yet.
+    async fn insert<'a>(&mut self, entries: &[&'a Entry]) -> Result<Vec<&'a Entry>, SinkError> {
+        let mut pending = VecDeque::new();
+        pending.push_back(entries);
+        while let Some(chunk) = pending.pop_front() {
+            if chunk.is_empty() {
+                continue;
+            }
+            let result = match self.try_insert(chunk).await {
+                Err(err) if !is_transient(&err) && chunk.len() == 1 && chunk[0].records().len() > 1 => {
+                    log::warn!("insert batch of {} records, error: {}, isolate", chunk[0].records().len(), err);
+                    match self.insert_isolating(chunk[0]).await {
+                        Ok(failed) => {
+                            for (record, err) in failed {
+                                self.dead_letter.write(record, &err)?;
+                            }
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+                result => result,
+            };
+            match result {
+                Ok(()) => {}
+                Err(err) if is_transient(&err) => {
+                    log::error!("insert {} entries, error: {}", chunk.len(), err);
+                    let mut unwritten = chunk.to_vec();
+                    unwritten.extend(pending.into_iter().flatten().copied());
+                    return Ok(unwritten);
+                }
+                Err(err) if chunk.len() == 1 => self.dead_letter.write(chunk[0], &err.to_string())?,
+                Err(err) => {
+                    log::warn!("insert {} entries, error: {}, bisect", chunk.len(), err);
+                    let (left, right) = chunk.split_at(chunk.len() / 2);
+                    pending.push_front(right);
+                    pending.push_front(left);
+                }
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// Execute the insertions of `entries` in pipeline, within one transaction
+    async fn try_insert(&mut self, entries: &[&Entry]) -> Result<(), Error> {
+        let mut prepared = Vec::with_capacity(entries.len());
+        for entry in entries.iter().flat_map(|entry| entry.records()) {
+            let (sql, params) = insert_statement(entry);
+            prepared.push((entry, self.prepare(sql).await?, params));
+        }
+
+        let transaction = self.client.transaction().await?;
+        execute_all(&transaction, &prepared).await?;
+        transaction.commit().await
+    }
+
+    /// Insert the records of a failed `batch` within one transaction, bisecting them within
+    /// savepoints until the failing records are isolated. The others, e.g. the checkpoint, are
+    /// committed together; return the failing ones with their errors.
+    async fn insert_isolating<'a>(&mut self, batch: &'a Entry) -> Result<Vec<(&'a Entry, String)>, Error> {
+        let mut prepared = Vec::with_capacity(batch.records().len());
+        for record in batch.records() {
+            let (sql, params) = insert_statement(record);
+            prepared.push((record, self.prepare(sql).await?, params));
+        }
+
+        let mut failed = Vec::new();
+        let mut transaction = self.client.transaction().await?;
+        let mut pending = VecDeque::new();
+        pending.push_back(&prepared[..]);
+        while let Some(chunk) = pending.pop_front() {
+            if chunk.is_empty() {
+                continue;
+            }
+            let savepoint = transaction.transaction().await?;
+            match execute_all(&savepoint, chunk).await {
+                Ok(()) => savepoint.commit().await?,
+                Err(err) if is_transient(&err) => return Err(err),
+                Err(err) => {
+                    savepoint.rollback().await?;
+                    if chunk.len() == 1 {
+                        failed.push((chunk[0].0, err.to_string()));
+                    } else {
+                        let (left, right) = chunk.split_at(chunk.len() / 2);
+                        pending.push_front(right);
+                        pending.push_front(left);
+                    }
+                }
+            }
+        }
+        transaction.commit().await?;
+        Ok(failed)
+    }
+
+    /// Bulk load the copyable records of `batches`, and insert their other records, e.g. the
+    /// checkpoints, within one transaction. Return the number of copied rows.
+    async fn copy(&mut self, batches: &[&Entry]) -> Result<u64, Error> {
+        // #{ (schema, table) => records }
+        let mut copies: HashMap<(&str, CopyTable), Vec<&Entry>> = HashMap::new();
+        let mut prepared = Vec::new();
+        for record in batches.iter().flat_map(|batch| batch.records()) {
+            match CopyTable::of(record) {
+                Some(key) => copies.entry(key).or_default().push(record),
+                None => {
+                    let (sql, params) = insert_statement(record);
+                    prepared.push((self.prepare(sql).await?, params));
+                }
+            }
+        }
+
+        let transaction = self.client.transaction().await?;
+        let mut n = 0;
+        for ((schema, table), records) in copies {
+            n += copy_in(&transaction, schema, table, &records).await?;
+        }
+        for (statement, params) in prepared.iter() {
+            transaction.execute(statement, &as_sql_params(params)).await?;
+        }
+        transaction.commit().await?;
+        Ok(n)
+    }
+
+    // Return 0 if the table is empty
+    async fn select_last_number(&self, statement: &str) -> Result<u64, Error> {
+        let row = self.client.query_opt(statement, &[]).await?;
+        Ok(row
+            .and_then(|row| row.get::<_, Option<i64>>(0))
+            .map(|number| number as u64)
+            .unwrap_or(0))
+    }
+}
+
+#[async_trait]
+impl Sink for PostgresSink {
+    async fn write_batch(&mut self, entries: &[Entry]) -> Result<(), SinkError> {
+        if Instant::now() < self.retry_at {
+            self.backlog.push(entries.to_vec())?;
+            return Ok(());
+        }
+        if !self.ensure_connected().await {
+            self.backlog.push(entries.to_vec())?;
+            self.back_off();
+            return Ok(());
+        }
+
+        // Flush the backlog first to keep entries in order
+        while let Some(chunk) = self.backlog.pop(BACKLOG_CHUNK_SIZE)? {
+            let unwritten = self.write_entries(&chunk).await?;
+            if !unwritten.is_empty() {
+                self.backlog.push_front(unwritten);
+                self.backlog.push(entries.to_vec())?;
+                self.back_off();
+                return Ok(());
+            }
+            self.backlog.ack()?;
+        }
+
+        let unwritten = self.write_entries(entries).await?;
+        if unwritten.is_empty() {
+            self.retry_backoff = INITIAL_BACKOFF;
+        } else {
+            self.backlog.push(unwritten)?;
+            self.back_off();
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self, timeout: Duration) -> Result<(), SinkError> {
+        let deadline = Instant::now() + timeout;
+        while !self.backlog.is_empty() {
+            if max(self.retry_at, Instant::now()) >= deadline {
+                return Err(format!("{} entries left in backlog", self.backlog.len()).into());
+            }
+            tokio::time::sleep_until(self.retry_at.into()).await;
+            self.write_batch(&[]).await?;
+        }
+        Ok(())
+    }
+
+    /// Return the checkpoint of `topic`, or for the schemas predating checkpoints, the last
+    /// number in its table
+    async fn last_number(&self, network: &str, topic: &str) -> Result<u64, SinkError> {
+        let checkpoint = self
+            .client
+            .query_opt(
+                format!("SELECT number FROM {}.checkpoint WHERE topic = $1", network).as_str(),
+                &[&topic],
+            )
+            .await?;
+        if let Some(row) = checkpoint {
+            return Ok(row.get::<_, i64>(0) as u64);
+        }
+
+        let statement = match topic {
+            "ChainCrawler" => format!("SELECT number FROM {}.block ORDER BY number DESC LIMIT 1", network),
+            "EpochCrawler" => format!("SELECT number FROM {}.epoch ORDER BY number DESC LIMIT 1", network),
+            "CellCrawler" => format!(
+                "SELECT GREATEST(\
+                    (SELECT MAX(block_number) FROM {0}.created_cell), \
+                    (SELECT MAX(block_number) FROM {0}.spent_cell))",
+                network
+            ),
+            "ChainTransactionCrawler" => format!(
+                "SELECT number FROM {}.block_transaction ORDER BY number DESC LIMIT 1",
+                network
+            ),
+            _ => return Ok(0),
+        };
+        Ok(self.select_last_number(&statement).await?)
+    }
+
+    /// ChainCrawler's blocks are in `block`, CellCrawler's in `created_cell`, whose rows
+    /// predating `block_hash` are skipped
+    async fn recent_block_hashes(
+        &self,
+        network: &str,
+        topic: &str,
+        number: u64,
+        limit: usize,
+    ) -> Result<Vec<(u64, Byte32)>, SinkError> {
+        let statement = match topic {
+            "ChainCrawler" => format!(
+                "SELECT number, hash FROM {}.block \
+                WHERE number <= $1 AND hash IS NOT NULL \
+                ORDER BY number DESC LIMIT $2",
+                network
+            ),
+            "CellCrawler" => format!(
+                "SELECT DISTINCT ON (block_number) block_number, block_hash FROM {}.created_cell \
+                WHERE block_number <= $1 AND block_hash IS NOT NULL \
+                ORDER BY block_number DESC LIMIT $2",
+                network
+            ),
+            _ => return Ok(Vec::new()),
+        };
+        let rows = self
+            .client
+            .query(statement.as_str(), &[&(number as i64), &(limit as i64)])
+            .await?;
+        let mut hashes = rows
+            .into_iter()
+            .filter_map(|row| {
+                let number = row.get::<_, i64>(0) as u64;
+                let hash = H256::from_str(row.get::<_, &str>(1).trim_start_matches("0x")).ok()?;
+                Some((number, hash.pack()))
+            })
+            .collect::<Vec<_>>();
+        hashes.reverse();
+        Ok(hashes)
+    }
+
+    async fn migrate(&mut self, network: &str, apply: bool) -> Result<(), SinkError> {
+        if apply {
+            let applied = migration::migrate(&mut self.client, network).await?;
+            if !applied.is_empty() {
+                // Prepared statements may refer to the altered tables
+                self.statements.clear();
+            }
+        } else {
+            let pending = migration::pending(&self.client, network).await?;
+            if !pending.is_empty() {
+                return Err(format!(
+                    "schema {} has {} pending migrations, run `ckb-analyzer migrate` or set `auto_migrate = true`",
+                    network,
+                    pending.len()
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn connect_client(config: &tokio_postgres::Config) -> Result<Client, Error> {
+    log::info!("Connecting to Postgres, {:?}", config);
+    let (client, conn) = config.connect(tokio_postgres::NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            log::error!("postgres connection error: {}", err);
+        }
+    });
+    Ok(client)
+}
+
+/// Connection failures, serialization failures, deadlocks, resource exhaustion and server
+/// shutdowns are worth retrying; errors caused by the data itself are not.
+fn is_transient(err: &Error) -> bool {
+    if err.is_closed() {
+        return true;
+    }
+    match err.code() {
+        Some(state) => matches!(&state.code()[..2], "08" | "40" | "53" | "57"),
+        None => err
+            .source()
+            .map(|source| source.is::<std::io::Error>())
+            .unwrap_or(false),
+    }
+}
+
+/// Execute the prepared insertions in pipeline within `transaction`
+async fn execute_all(
+    transaction: &Transaction<'_>,
+    prepared: &[(&Entry, Statement, Params)],
+) -> Result<(), Error> {
+    let results = futures::future::join_all(prepared.iter().map(|(_, statement, params)| async move {
+        transaction.execute(statement, &as_sql_params(params)).await
+    }))
+    .await;
+    // Statements run in order, the first error is the cause of the later ones
+    for result in results {
+        result?;
+    }
+    Ok(())
+}
+
+fn as_sql_params(params: &Params) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+        .collect()
+}
+
+/// Binary COPY `records` into a temporary staging table, then move them into `table`,
+/// skipping the existing rows just like the insertions do.
+async fn copy_in(
+    transaction: &Transaction<'_>,
+    schema: &str,
+    table: CopyTable,
+    records: &[&Entry],
+) -> Result<u64, Error> {
+    let staging = format!("{}_{}_staging", schema, table.name());
+    transaction
+        .batch_execute(&format!(
+            "CREATE TEMP TABLE IF NOT EXISTS {staging} (LIKE {schema}.{table} INCLUDING DEFAULTS) ON COMMIT DELETE ROWS",
+            staging = staging,
+            schema = schema,
+            table = table.name(),
+        ))
+        .await?;
+
+    let copy_in = transaction
+        .copy_in(&format!(
+            "COPY {} ({}) FROM STDIN BINARY",
+            staging,
+            table.columns()
+        ))
+        .await?;
+    let writer = BinaryCopyInWriter::new(copy_in, table.types());
+    futures::pin_mut!(writer);
+    for record in records {
+        let (_, params) = insert_statement(record);
+        writer.as_mut().write(&as_sql_params(&params)).await?;
+    }
+    writer.finish().await?;
+
+    transaction
+        .execute(
+            &format!(
+                "INSERT INTO {schema}.{table} ({columns}) SELECT {columns} FROM {staging} ON CONFLICT DO NOTHING",
+                schema = schema,
+                table = table.name(),
+                columns = table.columns(),
+                staging = staging,
+            ),
+            &[],
+        )
+        .await
+}
+
+fn insert_statement(entry: &Entry) -> (String, Params) {
+    match entry {
+        Entry::Peer(entry) => (
+            format!(
+                "INSERT INTO {}.peer(time, version, ip, n_reachable, address, peer_id, node_type, addresses) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+                ON CONFLICT (peer_id) DO UPDATE SET time = excluded.time, n_reachable = excluded.n_reachable, \
+                ip = excluded.ip, address = excluded.address, \
+                addresses = ARRAY(SELECT DISTINCT unnest(peer.addresses || excluded.addresses)), \
+                version = COALESCE(NULLIF(excluded.version, ''), peer.version), \
+                node_type = CASE excluded.node_type WHEN 0 THEN peer.node_type ELSE excluded.node_type END",
+                entry.network
+            ),
+            vec![
+                Box::new(chrono::DateTime::<chrono::Utc>::from_utc(entry.time, chrono::Utc)),
+                Box::new(entry.version.clone()),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.n_reachable),
+                Box::new(entry.address.clone()),
+                Box::new(entry.peer_id.clone()),
+                Box::new(entry.node_type as i32),
+                Box::new(entry.addresses.clone()),
+            ],
+        ),
+        Entry::PeerSession(entry) => (
+            format!(
+                "INSERT INTO {}.peer_session_history(time, event, ip, address, peer_id, joined_at, uptime) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.event.clone()),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.address.clone()),
+                Box::new(entry.peer_id.clone()),
+                Box::new(entry.joined_at),
+                Box::new(entry.uptime),
+            ],
+        ),
+        Entry::PeerEdge(entry) => (
+            format!(
+                "INSERT INTO {}.peer_edge(src_ip, dst_ip, first_seen, last_seen) VALUES ($1, $2, $3, $3) \
+                ON CONFLICT (src_ip, dst_ip) DO UPDATE SET last_seen = GREATEST(peer_edge.last_seen, excluded.last_seen)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.src_ip.clone()),
+                Box::new(entry.dst_ip.clone()),
+                Box::new(entry.time),
+            ],
+        ),
+        Entry::CrawlRound(entry) => (
+            format!(
+                "INSERT INTO {}.crawl_round(time, n_known, n_dialed, n_succeeded, n_failures, n_never_dialed, n_backing_off) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.n_known),
+                Box::new(entry.n_dialed),
+                Box::new(entry.n_succeeded),
+                Box::new(entry.n_failures),
+                Box::new(entry.n_never_dialed),
+                Box::new(entry.n_backing_off),
+            ],
+        ),
+        Entry::TransportReachability(entry) => (
+            format!(
+                "INSERT INTO {}.transport_reachability(time, transport, n_known, n_dialed, n_succeeded) \
+                VALUES ($1, $2, $3, $4, $5)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.transport.clone()),
+                Box::new(entry.n_known),
+                Box::new(entry.n_dialed),
+                Box::new(entry.n_succeeded),
+            ],
+        ),
+        Entry::AddressProbe(entry) => (
+            format!(
+                "INSERT INTO {}.address_probe(time, ip, address, peer_id, outcome, latency) \
+                VALUES ($1, $2, $3, $4, $5, $6)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.address.clone()),
+                Box::new(entry.peer_id.clone()),
+                Box::new(entry.outcome.clone()),
+                Box::new(entry.latency),
+            ],
+        ),
+        Entry::PeerLatency(entry) => (
+            format!(
+                "INSERT INTO {}.peer_latency(time, peer_id, ip, metric, n_samples, p50, p90, p99) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.peer_id.clone()),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.metric.clone()),
+                Box::new(entry.n_samples),
+                Box::new(entry.p50),
+                Box::new(entry.p90),
+                Box::new(entry.p99),
+            ],
+        ),
+        Entry::InboundPeer(entry) => (
+            format!(
+                "INSERT INTO {}.inbound_peer(first_seen, last_seen, peer_id, ip, address, name, client_version, flag, services, listen_addrs, reachable, dialback_addr) \
+                VALUES ($1, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) \
+                ON CONFLICT (peer_id) DO UPDATE SET last_seen = excluded.last_seen, ip = excluded.ip, address = excluded.address, \
+                name = excluded.name, client_version = excluded.client_version, flag = excluded.flag, services = excluded.services, \
+                listen_addrs = excluded.listen_addrs, reachable = excluded.reachable, dialback_addr = excluded.dialback_addr",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.peer_id.clone()),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.address.clone()),
+                Box::new(entry.name.clone()),
+                Box::new(entry.client_version.clone()),
+                Box::new(entry.flag),
+                Box::new(entry.services.clone()),
+                Box::new(entry.listen_addrs.clone()),
+                Box::new(entry.reachable),
+                Box::new(entry.dialback_addr.clone()),
+            ],
+        ),
+        Entry::PeerIdentify(entry) => (
+            format!(
+                "INSERT INTO {}.peer_identify(time, ip, address, name, client_version, flag, services, listen_addrs, observed_addr, protocols) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+                ON CONFLICT (address) DO UPDATE SET time = excluded.time, ip = excluded.ip, name = excluded.name, \
+                client_version = excluded.client_version, flag = excluded.flag, services = excluded.services, \
+                listen_addrs = excluded.listen_addrs, observed_addr = excluded.observed_addr, protocols = excluded.protocols",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.address.clone()),
+                Box::new(entry.name.clone()),
+                Box::new(entry.client_version.clone()),
+                Box::new(entry.flag),
+                Box::new(entry.services.clone()),
+                Box::new(entry.listen_addrs.clone()),
+                Box::new(entry.observed_addr.clone()),
+                Box::new(entry.protocols.clone()),
+            ],
+        ),
+        Entry::Snapshot(entry) => (
+            format!(
+                "INSERT INTO {}.snapshot(id, started_at, finished_at, complete, n_peers, n_edges) \
+                VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.id),
+                Box::new(entry.started_at),
+                Box::new(entry.finished_at),
+                Box::new(entry.complete),
+                Box::new(entry.n_peers),
+                Box::new(entry.n_edges),
+            ],
+        ),
+        Entry::SnapshotPeer(entry) => (
+            format!(
+                "INSERT INTO {}.snapshot_peer(snapshot_id, ip, address, peer_id, version, node_type, n_reachable) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.snapshot_id),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.address.clone()),
+                Box::new(entry.peer_id.clone()),
+                Box::new(entry.version.clone()),
+                Box::new(entry.node_type as i32),
+                Box::new(entry.n_reachable),
+            ],
+        ),
+        Entry::SnapshotEdge(entry) => (
+            format!(
+                "INSERT INTO {}.snapshot_edge(snapshot_id, src_ip, dst_ip) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.snapshot_id),
+                Box::new(entry.src_ip.clone()),
+                Box::new(entry.dst_ip.clone()),
+            ],
+        ),
+        Entry::GeoCity(entry) => (
+            format!(
+                "INSERT INTO {}.geo_city(snapshot_id, country, region, city, latitude, longitude, version, n_nodes) \
+                VALUES ($1, $2, $3, $4, $5::FLOAT8, $6::FLOAT8, $7, $8) ON CONFLICT DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.snapshot_id),
+                Box::new(entry.country.clone()),
+                Box::new(entry.region.clone()),
+                Box::new(entry.city.clone()),
+                Box::new(entry.latitude),
+                Box::new(entry.longitude),
+                Box::new(entry.version.clone()),
+                Box::new(entry.n_nodes),
+            ],
+        ),
+        Entry::IpInfo(entry) => (
+            // Refreshes keep the previous values of what could not be resolved this time
+            format!(
+                "INSERT INTO {0}.ipinfo(ip, country, city, region, company, latitude, longitude, asn, as_organization, provider, hosting_class) \
+                VALUES ($1, $2, $3, $4, $5, $6::FLOAT8, $7::FLOAT8, $8, $9, $10, $11) \
+                ON CONFLICT (ip) DO UPDATE SET \
+                country = COALESCE(NULLIF(EXCLUDED.country, ''), {0}.ipinfo.country), \
+                city = COALESCE(NULLIF(EXCLUDED.city, ''), {0}.ipinfo.city), \
+                region = COALESCE(NULLIF(EXCLUDED.region, ''), {0}.ipinfo.region), \
+                company = COALESCE(NULLIF(EXCLUDED.company, ''), {0}.ipinfo.company), \
+                latitude = COALESCE(EXCLUDED.latitude, {0}.ipinfo.latitude), \
+                longitude = COALESCE(EXCLUDED.longitude, {0}.ipinfo.longitude), \
+                asn = COALESCE(EXCLUDED.asn, {0}.ipinfo.asn), \
+                as_organization = COALESCE(EXCLUDED.as_organization, {0}.ipinfo.as_organization), \
+                provider = COALESCE(EXCLUDED.provider, {0}.ipinfo.provider), \
+                hosting_class = COALESCE(EXCLUDED.hosting_class, {0}.ipinfo.hosting_class)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.ip.clone()),
+                Box::new(entry.country.clone()),
+                Box::new(entry.city.clone()),
+                Box::new(entry.region.clone()),
+                Box::new(entry.company.clone()),
+                Box::new(entry.latitude),
+                Box::new(entry.longitude),
+                Box::new(entry.asn),
+                Box::new(entry.as_organization.clone()),
+                Box::new(entry.provider.clone()),
+                Box::new(entry.hosting_class.clone()),
+            ],
+        ),
+        Entry::Block(entry) => (
+            format!(
+                "INSERT INTO {}.block(time, number, n_transactions, n_proposals, n_uncles, miner_lock_args, cellbase_client_version, cellbase_miner_source, interval, hash) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+                ON CONFLICT (number) DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.number),
+                Box::new(entry.n_transactions),
+                Box::new(entry.n_proposals),
+                Box::new(entry.n_uncles),
+                Box::new(entry.miner_lock_args.clone()),
+                Box::new(entry.cellbase_client_version.clone()),
+                Box::new(entry.cellbase_miner_source.clone()),
+                Box::new(entry.interval),
+                Box::new(format!("{:#x}", entry.hash)),
+            ],
+        ),
+        Entry::Epoch(entry) => (
+            format!(
+                "INSERT INTO {}.epoch(start_time, end_time, number, length, start_number, n_uncles, difficulty) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7::TEXT::NUMERIC) \
+                ON CONFLICT (number) DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.start_time),
+                Box::new(entry.end_time),
+                Box::new(entry.number as i64),
+                Box::new(entry.length as i64),
+                Box::new(entry.start_number as i64),
+                Box::new(entry.n_uncles),
+                Box::new(entry.difficulty.clone()),
+            ],
+        ),
+        Entry::TxPoolInfo(entry) => (
+            format!(
+                "INSERT INTO {}.tx_pool_info(time, total_tx_cycles, total_tx_size, pending, proposed, orphan) \
+                VALUES ($1, $2, $3, $4, $5, $6)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.total_tx_cycles),
+                Box::new(entry.total_tx_size),
+                Box::new(entry.pending),
+                Box::new(entry.proposed),
+                Box::new(entry.orphan),
+            ],
+        ),
+        Entry::BlockTransaction(entry) => (
+            format!(
+                "INSERT INTO {}.block_transaction(time, number, size, n_inputs, n_outputs, n_header_deps, n_cell_deps, total_data_size, proposal_id, hash) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+                ON CONFLICT (number) DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.number),
+                Box::new(entry.size as i64),
+                Box::new(entry.n_inputs),
+                Box::new(entry.n_outputs),
+                Box::new(entry.n_header_deps),
+                Box::new(entry.n_cell_deps),
+                Box::new(entry.total_data_size as i64),
+                Box::new(entry.proposal_id.clone()),
+                Box::new(entry.hash.clone()),
+            ],
+        ),
+        Entry::SubscribedNewTransaction(entry) => (
+            format!(
+                "INSERT INTO {}.subscribed_new_transaction(time, size, cycles, fee, n_inputs, n_outputs, n_cell_deps, n_header_deps, hash, proposal_id) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.size as i64),
+                Box::new(entry.cycles as i64),
+                Box::new(entry.fee as i64),
+                Box::new(entry.n_inputs as i32),
+                Box::new(entry.n_outputs as i32),
+                Box::new(entry.n_cell_deps as i32),
+                Box::new(entry.n_header_deps as i32),
+                Box::new(format!("{:#x}", entry.hash)),
+                Box::new(format!("{:#x}", entry.proposal_id)),
+            ],
+        ),
+        Entry::SubscribedProposedTransaction(entry) => (
+            format!(
+                "INSERT INTO {}.subscribed_proposed_transaction(time, size, cycles, fee, n_inputs, n_outputs, n_cell_deps, n_header_deps, hash, proposal_id) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.size as i64),
+                Box::new(entry.cycles as i64),
+                Box::new(entry.fee as i64),
+                Box::new(entry.n_inputs as i32),
+                Box::new(entry.n_outputs as i32),
+                Box::new(entry.n_cell_deps as i32),
+                Box::new(entry.n_header_deps as i32),
+                Box::new(format!("{:#x}", entry.hash)),
+                Box::new(format!("{:#x}", entry.proposal_id)),
+            ],
+        ),
+        Entry::SubscribedRejectedTransaction(entry) => (
+            format!(
+                "INSERT INTO {}.subscribed_rejected_transaction(time, reason, size, cycles, fee, n_inputs, n_outputs, n_cell_deps, n_header_deps, hash, proposal_id) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.reason.clone()),
+                Box::new(entry.size as i64),
+                Box::new(entry.cycles as i64),
+                Box::new(entry.fee as i64),
+                Box::new(entry.n_inputs as i32),
+                Box::new(entry.n_outputs as i32),
+                Box::new(entry.n_cell_deps as i32),
+                Box::new(entry.n_header_deps as i32),
+                Box::new(format!("{:#x}", entry.hash)),
+                Box::new(format!("{:#x}", entry.proposal_id)),
+            ],
+        ),
+        Entry::RetentionTransaction(entry) => (
+            format!(
+                "INSERT INTO {}.retention_transaction(time, hash) VALUES ($1, $2)",
+                entry.network
+            ),
+            vec![Box::new(entry.time), Box::new(format!("{:#x}", entry.hash))],
+        ),
+        Entry::CreatedCell(entry) => (
+            format!(
+                "INSERT INTO {}.created_cell(time, block_number, tx_index, tx_hash, index, lock_hash_type, lock_code_hash, lock_args, type_hash_type, type_code_hash, block_hash) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) \
+                ON CONFLICT DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.block_number as i64),
+                Box::new(entry.tx_index as i32),
+                Box::new(format!("{:#x}", entry.out_point.tx_hash())),
+                Box::new(Unpack::<u32>::unpack(&entry.out_point.index()) as i32),
+                Box::new(Into::<u8>::into(entry.lock_hash_type) as i32),
+                Box::new(format!("{:#x}", entry.lock_code_hash)),
+                Box::new(
+                    entry
+                        .lock_args
+                        .as_ref()
+                        .map(|args| format!("{:#x}", args))
+                        .unwrap_or_default(),
+                ),
+                Box::new(
+                    entry
+                        .type_hash_type
+                        .map(|t| Into::<u8>::into(t))
+                        .unwrap_or(u8::max_value()) as i32,
+                ),
+                Box::new(
+                    entry
+                        .type_code_hash
+                        .as_ref()
+                        .map(|hash| format!("{:#x}", hash))
+                        .unwrap_or_default(),
+                ),
+                Box::new(format!("{:#x}", entry.block_hash)),
+            ],
+        ),
+        Entry::SpentCell(entry) => (
+            format!(
+                "INSERT INTO {}.spent_cell(time, block_number, tx_hash, index) \
+                VALUES ($1, $2, $3, $4) \
+                ON CONFLICT DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.block_number as i64),
+                Box::new(format!("{:#x}", entry.out_point.tx_hash())),
+                Box::new(Unpack::<u32>::unpack(&entry.out_point.index()) as i64),
+            ],
+        ),
+        Entry::CompactBlockFirstSeen(entry) => (
+            format!(
+                "INSERT INTO {}.compact_block_first_seen(time, block_number, ip) VALUES ($1, $2, $3)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.block_number as i64),
+                Box::new(entry.ip.clone()),
+            ],
+        ),
+        Entry::PeerLastCompactBlock(entry) => (
+            format!(
+                "INSERT INTO {}.peer_last_compact_block(ip, block_number, block_hash, time) \
+                VALUES ($1, $2, $3, $4) \
+                ON CONFLICT (ip) \
+                DO UPDATE SET (block_number, block_hash, time) = (EXCLUDED.block_number, EXCLUDED.block_hash, EXCLUDED.time)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.ip.clone()),
+                Box::new(entry.block_number as i64),
+                Box::new(format!("{:#x}", entry.block_hash)),
+                Box::new(entry.time),
+            ],
+        ),
+        Entry::BlockPropagationDelay(entry) => (
+            format!(
+                "INSERT INTO {}.block_propagation_delay(time, block_number, block_hash, ip, delay, header_delay) \
+                VALUES ($1, $2, $3, $4, $5, $6)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.block_number as i64),
+                Box::new(format!("{:#x}", entry.block_hash)),
+                Box::new(entry.ip.clone()),
+                Box::new(entry.delay),
+                Box::new(entry.header_delay),
+            ],
+        ),
+        Entry::BlockPropagation(entry) => (
+            format!(
+                "INSERT INTO {}.block_propagation(time, block_number, block_hash, first_ip, n_peers, p50, p90, p99, header_p50, header_p90, header_p99) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT DO NOTHING",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.block_number as i64),
+                Box::new(format!("{:#x}", entry.block_hash)),
+                Box::new(entry.first_ip.clone()),
+                Box::new(entry.n_peers),
+                Box::new(entry.p50),
+                Box::new(entry.p90),
+                Box::new(entry.p99),
+                Box::new(entry.header_p50),
+                Box::new(entry.header_p90),
+                Box::new(entry.header_p99),
+            ],
+        ),
+        Entry::Reorg(entry) => (
+            format!(
+                "INSERT INTO {}.reorg(time, topic, fork_number, depth, old_tip_number, old_tip_hash, new_tip_hash) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.time),
+                Box::new(entry.topic.clone()),
+                Box::new(entry.fork_number as i64),
+                Box::new(entry.depth as i64),
+                Box::new(entry.old_tip_number as i64),
+                Box::new(format!("{:#x}", entry.old_tip_hash)),
+                Box::new(format!("{:#x}", entry.new_tip_hash)),
+            ],
+        ),
+        // Move the checkpoint back along with the rows, in case of crash before re-crawling
+        Entry::Rollback(entry) => (
+            match entry.topic.as_str() {
+                "ChainCrawler" => format!(
+                    "WITH block AS (DELETE FROM {0}.block WHERE number > $1) \
+                    UPDATE {0}.checkpoint SET number = $1 WHERE topic = $2 AND number > $1",
+                    entry.network
+                ),
+                "CellCrawler" => format!(
+                    "WITH created AS (DELETE FROM {0}.created_cell WHERE block_number > $1), \
+                    spent AS (DELETE FROM {0}.spent_cell WHERE block_number > $1) \
+                    UPDATE {0}.checkpoint SET number = $1 WHERE topic = $2 AND number > $1",
+                    entry.network
+                ),
+                topic => unreachable!("{} does not roll back", topic),
+            },
+            vec![Box::new(entry.fork_number as i64), Box::new(entry.topic.clone())],
+        ),
+        Entry::Checkpoint(entry) => (
+            format!(
+                "INSERT INTO {}.checkpoint(topic, number, time) VALUES ($1, $2, $3) \
+                ON CONFLICT (topic) DO UPDATE SET number = excluded.number, time = excluded.time",
+                entry.network
+            ),
+            vec![
+                Box::new(entry.topic.clone()),
+                Box::new(entry.number as i64),
+                Box::new(entry.time),
+            ],
+        ),
+        Entry::Batch { .. } => unreachable!("batches are unwrapped before insertion"),
+    }
+}
+
+#[test]
+fn test_copy_tables_match_insertions() {
+    use crate::ckb_types::{
+        core::ScriptHashType,
+        packed::{Byte32, OutPoint},
+    };
+    use crate::entry;
+
+    let time = chrono::NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap();
+    let entries: Vec<Entry> = vec![
+        entry::CreatedCell {
+            network: "ckb".to_string(),
+            time,
+            block_number: 1,
+            block_hash: Byte32::zero(),
+            tx_index: 0,
+            out_point: OutPoint::new(Byte32::zero(), 0),
+            lock_hash_type: ScriptHashType::Type,
+            lock_code_hash: Byte32::zero(),
+            lock_args: None,
+            type_hash_type: None,
+            type_code_hash: None,
+        }
+        .into(),
+        entry::SpentCell {
+            network: "ckb".to_string(),
+            time,
+            block_number: 1,
+            out_point: OutPoint::new(Byte32::zero(), 0),
+        }
+        .into(),
+        entry::BlockTransaction {
+            network: "ckb".to_string(),
+            time,
+            number: 1,
+            size: 0,
+            n_inputs: 0,
+            n_outputs: 0,
+            n_header_deps: 0,
+            n_cell_deps: 0,
+            total_data_size: 0,
+            proposal_id: String::new(),
+            hash: String::new(),
+        }
+        .into(),
+    ];
+    for entry in entries.iter() {
+        let (schema, table) = CopyTable::of(entry).unwrap();
+        let (sql, params) = insert_statement(entry);
+        assert_eq!(schema, "ckb");
+        assert!(sql.contains(&format!("{}.{}({})", schema, table.name(), table.columns())));
+        assert_eq!(params.len(), table.types().len());
+    }
+}
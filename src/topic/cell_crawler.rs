@@ -1,51 +1,118 @@
 use crate::ckb_types::{
     core::{BlockNumber, BlockView},
     h256,
-    packed::OutPoint,
+    packed::{Byte32, OutPoint},
     prelude::*,
 };
 use crate::entry;
+use crate::util::reorg::{Reorg, ReorgDetector, REORG_DETECTION_WINDOW};
 use ckb_testkit::Node;
 use std::cmp::max;
 use std::convert::TryInto;
 use std::time::Duration;
 
 const BLOCK_CONFIRMATION: BlockNumber = 10;
+// Blocks further than `BACKFILL_DISTANCE` from node's tip are sent as backfill batches, which
+// the Postgres sink bulk loads with COPY
+const BACKFILL_DISTANCE: BlockNumber = 1000;
 
 pub struct CellCrawler {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
+    interval: Duration,
 }
 
 impl CellCrawler {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(
+        node: Node,
+        entry_sender: crossbeam::channel::Sender<entry::Entry>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            node,
+            entry_sender,
+            interval,
+        }
     }
 
-    pub async fn run(&self, last_cell_block_number: BlockNumber) {
+    // `recent_hashes` are the blocks stored before, to detect the reorgs happened while stopped
+    pub async fn run(
+        &self,
+        last_cell_block_number: BlockNumber,
+        recent_hashes: Vec<(BlockNumber, Byte32)>,
+    ) {
         let mut current_number = max(1, last_cell_block_number + 1);
         let mut tip_number = self.node.get_tip_block_number();
+        let mut reorg_detector = ReorgDetector::new(REORG_DETECTION_WINDOW);
+        reorg_detector.seed(recent_hashes);
         loop {
             // Keep `BLOCK_CONFIRMATION` distance with node's tip
             if current_number >= tip_number - BLOCK_CONFIRMATION {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                tokio::time::sleep(self.interval).await;
                 tip_number = self.node.get_tip_block_number();
                 continue;
             }
 
             let block = self.node.get_block_by_number(current_number);
-            self.analyze_block_cells(&block).await;
+            if let Some(reorg) =
+                reorg_detector.detect(block.number(), &block.parent_hash(), |number| {
+                    self.node.get_header_by_number(number).hash()
+                })
+            {
+                self.rollback(&reorg).await;
+                current_number = reorg.fork_number + 1;
+                continue;
+            }
+            let backfill = tip_number - current_number > BACKFILL_DISTANCE;
+            self.analyze_block_cells(&block, backfill).await;
+            reorg_detector.record(block.number(), block.hash());
 
             current_number += 1;
         }
     }
 
-    async fn analyze_block_cells(&self, block: &BlockView) {
+    // Record the reorg and delete the cells of the orphaned blocks, the canonical ones are
+    // crawled again
+    async fn rollback(&self, reorg: &Reorg) {
+        log::warn!("CellCrawler detected {:?}, roll back", reorg);
+        let network = self.node.consensus().id.clone();
+        self.retry_send_entry(entry::Reorg {
+            network: network.clone(),
+            time: chrono::Utc::now().naive_utc(),
+            topic: "CellCrawler".to_string(),
+            fork_number: reorg.fork_number,
+            depth: reorg.depth(),
+            old_tip_number: reorg.old_tip_number,
+            old_tip_hash: reorg.old_tip_hash.clone(),
+            new_tip_hash: reorg.new_tip_hash.clone(),
+        })
+        .await;
+        self.retry_send_entry(entry::Rollback {
+            network,
+            topic: "CellCrawler".to_string(),
+            fork_number: reorg.fork_number,
+        })
+        .await;
+    }
+
+    async fn retry_send_entry(&self, entry: impl Into<entry::Entry>) {
+        let entry = entry.into();
+        loop {
+            match self.entry_sender.send(entry.clone()) {
+                Ok(_) => return,
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    async fn analyze_block_cells(&self, block: &BlockView, backfill: bool) {
         let time = chrono::NaiveDateTime::from_timestamp(
             (block.timestamp() / 1000) as i64,
             (block.timestamp() % 1000 * 1000) as u32,
         );
-        let mut queries = Vec::new();
+        let mut entries: Vec<entry::Entry> = Vec::new();
         for (tx_index, tx) in block.transactions().iter().enumerate() {
             let tx_hash = tx.hash();
             if tx_index != 0 {
@@ -56,16 +123,7 @@ impl CellCrawler {
                         block_number: block.number(),
                         out_point: input,
                     };
-                    let raw_query = format!(
-                        "INSERT INTO {}.spent_cell (time, block_number, tx_hash, index) \
-                    VALUES ('{}', {}, '{:#x}', {})",
-                        entry.network,
-                        entry.time,
-                        entry.block_number,
-                        entry.out_point.tx_hash(),
-                        Unpack::<u32>::unpack(&entry.out_point.index()),
-                    );
-                    queries.push(raw_query);
+                    entries.push(entry.into());
                 }
             }
 
@@ -75,6 +133,7 @@ impl CellCrawler {
                     network: self.node.consensus().id.clone(),
                     time,
                     block_number: block.number(),
+                    block_hash: block.hash(),
                     tx_index,
                     out_point,
                     lock_hash_type: output.lock().hash_type().try_into().unwrap(),
@@ -96,21 +155,25 @@ impl CellCrawler {
                         .map(|script| script.hash_type().try_into().unwrap()),
                     type_code_hash: output.type_().to_opt().map(|script| script.code_hash()),
                 };
-                let raw_query = format!(
-                    "INSERT INTO {}.created_cell (time, block_number, tx_index, tx_hash, index, lock_hash_type, lock_code_hash, lock_args, type_hash_type, type_code_hash) \
-                    VALUES ('{}', {}, {}, '{:#x}', {}, {}, '{:#x}', '{}', {}, '{}')",
-                    entry.network, entry.time, entry.block_number, entry.tx_index, entry.out_point.tx_hash(), Unpack::<u32>::unpack(&entry.out_point.index()),
-                    Into::<u8>::into(entry.lock_hash_type),
-                    entry.lock_code_hash,
-                    entry.lock_args.map(|h| format!("{:#x}", h)).unwrap_or_default(),
-                    entry.type_hash_type.map(|t| Into::<u8>::into(t)).unwrap_or(u8::max_value()),
-                    entry.type_code_hash.map(|h| format!("{:#x}", h)).unwrap_or_default(),
-                );
-                queries.push(raw_query);
+                entries.push(entry.into());
             }
         }
 
-        let batch_query = queries.join(";");
-        self.query_sender.send(batch_query).unwrap();
+        // Sent along with the block's records, so the sink persists them atomically
+        entries.push(
+            entry::Checkpoint {
+                network: self.node.consensus().id.clone(),
+                time: chrono::Utc::now().naive_utc(),
+                topic: "CellCrawler".to_string(),
+                number: block.number(),
+            }
+            .into(),
+        );
+
+        self.retry_send_entry(entry::Entry::Batch {
+            records: entries,
+            backfill,
+        })
+        .await;
     }
 }
@@ -4,6 +4,7 @@ use crate::ckb_types::{
     prelude::*,
 };
 use crate::entry;
+use crate::util::reorg::{Reorg, ReorgDetector, REORG_DETECTION_WINDOW};
 use ckb_testkit::Node;
 use std::cmp::max;
 use std::time::Duration;
@@ -12,27 +13,53 @@ const BLOCK_CONFIRMATION: BlockNumber = 10;
 
 pub struct ChainCrawler {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
+    interval: Duration,
 }
 
 impl ChainCrawler {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(
+        node: Node,
+        entry_sender: crossbeam::channel::Sender<entry::Entry>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            node,
+            entry_sender,
+            interval,
+        }
     }
 
-    pub async fn run(&self, last_block_number: BlockNumber) {
-        let mut current_number = max(1, last_block_number);
+    // `recent_hashes` are the blocks stored before, to detect the reorgs happened while stopped
+    pub async fn run(
+        &self,
+        last_block_number: BlockNumber,
+        recent_hashes: Vec<(BlockNumber, packed::Byte32)>,
+    ) {
+        let mut current_number = max(1, last_block_number + 1);
         let mut tip_number = self.node.get_tip_block_number();
+        let mut reorg_detector = ReorgDetector::new(REORG_DETECTION_WINDOW);
+        reorg_detector.seed(recent_hashes);
         loop {
             // Keep `BLOCK_CONFIRMATION` distance with node's tip
             if current_number >= tip_number - BLOCK_CONFIRMATION {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                tokio::time::sleep(self.interval).await;
                 tip_number = self.node.get_tip_block_number();
                 continue;
             }
 
             let block = self.node.get_block_by_number(current_number);
+            if let Some(reorg) =
+                reorg_detector.detect(block.number(), &block.parent_hash(), |number| {
+                    self.node.get_header_by_number(number).hash()
+                })
+            {
+                self.rollback(&reorg).await;
+                current_number = reorg.fork_number + 1;
+                continue;
+            }
             self.analyze_block(&block).await;
+            reorg_detector.record(block.number(), block.hash());
 
             current_number += 1;
         }
@@ -85,29 +112,47 @@ impl ChainCrawler {
             interval: interval as i64,
             hash: block.hash(),
         };
-        self.retry_send_entry_query(&entry).await;
+        let checkpoint = entry::Checkpoint {
+            network: self.node.consensus().id.clone(),
+            time: chrono::Utc::now().naive_utc(),
+            topic: "ChainCrawler".to_string(),
+            number,
+        };
+        // Sent along with the block, so the sink persists them atomically
+        self.retry_send_entry(entry::Entry::Batch {
+            records: vec![entry.into(), checkpoint.into()],
+            backfill: false,
+        })
+        .await;
     }
 
-    async fn retry_send_entry_query(&self, entry: &entry::Block) {
-        let query =
-            format!(
-                "INSERT INTO {}.block(time, number, n_transactions, n_proposals, n_uncles, miner_lock_args, cellbase_client_version, cellbase_miner_source, interval, hash) \
-            VALUES ('{}', {}, {}, {}, {}, '{}', '{}', '{}', {}, '{:#x}') \
-            ON CONFLICT (number) DO NOTHING",
-                entry.network,
-                entry.time,
-                entry.number,
-                entry.n_transactions,
-                entry.n_proposals,
-                entry.n_uncles,
-                entry.miner_lock_args,
-                entry.cellbase_client_version,
-                entry.cellbase_miner_source,
-                entry.interval,
-                entry.hash,
-        );
+    // Record the reorg and delete the orphaned blocks, the canonical ones are crawled again
+    async fn rollback(&self, reorg: &Reorg) {
+        log::warn!("ChainCrawler detected {:?}, roll back", reorg);
+        let network = self.node.consensus().id.clone();
+        self.retry_send_entry(entry::Reorg {
+            network: network.clone(),
+            time: chrono::Utc::now().naive_utc(),
+            topic: "ChainCrawler".to_string(),
+            fork_number: reorg.fork_number,
+            depth: reorg.depth(),
+            old_tip_number: reorg.old_tip_number,
+            old_tip_hash: reorg.old_tip_hash.clone(),
+            new_tip_hash: reorg.new_tip_hash.clone(),
+        })
+        .await;
+        self.retry_send_entry(entry::Rollback {
+            network,
+            topic: "ChainCrawler".to_string(),
+            fork_number: reorg.fork_number,
+        })
+        .await;
+    }
+
+    async fn retry_send_entry(&self, entry: impl Into<entry::Entry>) {
+        let entry = entry.into();
         loop {
-            match self.query_sender.send(query.clone()) {
+            match self.entry_sender.send(entry.clone()) {
                 Ok(_) => return,
                 Err(_) => {
                     tokio::time::sleep(Duration::from_secs(1)).await;
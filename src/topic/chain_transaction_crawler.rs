@@ -5,37 +5,50 @@ use std::cmp::max;
 use std::time::Duration;
 
 const BLOCK_CONFIRMATION: BlockNumber = 10;
+// Blocks further than `BACKFILL_DISTANCE` from node's tip are sent as backfill batches, which
+// the Postgres sink bulk loads with COPY
+const BACKFILL_DISTANCE: BlockNumber = 1000;
 
 pub struct ChainTransactionCrawler {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
+    interval: Duration,
 }
 
 impl ChainTransactionCrawler {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(
+        node: Node,
+        entry_sender: crossbeam::channel::Sender<entry::Entry>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            node,
+            entry_sender,
+            interval,
+        }
     }
 
     pub async fn run(&self, last_block_number: BlockNumber) {
-        let mut current_number = max(1, last_block_number);
+        let mut current_number = max(1, last_block_number + 1);
         let mut tip_number = self.node.get_tip_block_number();
         loop {
             // Keep `BLOCK_CONFIRMATION` distance with node's tip
             if current_number >= tip_number - BLOCK_CONFIRMATION {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                tokio::time::sleep(self.interval).await;
                 tip_number = self.node.get_tip_block_number();
                 continue;
             }
 
             let block = self.node.get_block_by_number(current_number);
-            self.analyze_block_transactions(&block).await;
+            let backfill = tip_number - current_number > BACKFILL_DISTANCE;
+            self.analyze_block_transactions(&block, backfill).await;
 
             current_number += 1;
         }
     }
 
-    async fn analyze_block_transactions(&self, block: &BlockView) {
-        let mut raw_queries = Vec::with_capacity(block.transactions().len());
+    async fn analyze_block_transactions(&self, block: &BlockView, backfill: bool) {
+        let mut entries: Vec<entry::Entry> = Vec::with_capacity(block.transactions().len());
         let time = chrono::NaiveDateTime::from_timestamp(
             (block.timestamp() / 1000) as i64,
             (block.timestamp() % 1000 * 1000) as u32,
@@ -60,25 +73,25 @@ impl ChainTransactionCrawler {
                 proposal_id: format!("{:#x}", tx.proposal_short_id()),
                 hash: format!("{:#x}", tx.hash()),
             };
-            let raw_query = format!(
-                "INSERT INTO {}.block_transaction(time, number, size, n_inputs, n_outputs, n_header_deps, n_cell_deps, total_data_size, proposal_id, hash) \
-                VALUES ('{}', {}, {}, {}, {}, {}, {}, {}, '{}', '{}') \
-                ON CONFLICT (number) DO NOTHING",
-                entry.network,
-                entry.time,
-                entry.number,
-                entry.size,
-                entry.n_inputs,
-                entry.n_outputs,
-                entry.n_header_deps,
-                entry.n_cell_deps,
-                entry.total_data_size,
-                entry.proposal_id,
-                entry.hash,
-            );
-            raw_queries.push(raw_query);
+            entries.push(entry.into());
         }
 
-        self.query_sender.send(raw_queries.join(";")).unwrap();
+        // Sent along with the block's records, so the sink persists them atomically
+        entries.push(
+            entry::Checkpoint {
+                network: self.node.consensus().id.clone(),
+                time: chrono::Utc::now().naive_utc(),
+                topic: "ChainTransactionCrawler".to_string(),
+                number: block.number(),
+            }
+            .into(),
+        );
+
+        self.entry_sender
+            .send(entry::Entry::Batch {
+                records: entries,
+                backfill,
+            })
+            .unwrap();
     }
 }
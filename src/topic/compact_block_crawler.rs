@@ -1,4 +1,9 @@
-use crate::util::{bootnodes::bootnodes, ipinfo::lookup_ipinfo, multiaddr::addr_to_ip};
+use crate::util::{
+    bootnodes::bootnodes,
+    enrichment::Enrichment,
+    multiaddr::addr_to_ip,
+    propagation::PropagationTracker,
+};
 use ckb_testkit::{
     ckb_types::{packed, prelude::*},
     compress,
@@ -26,7 +31,7 @@ use rand::{thread_rng, Rng};
 use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::num::NonZeroUsize;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 use tokio::runtime::Handle;
 use tokio_util::codec::{length_delimited::LengthDelimitedCodec, Decoder, Encoder};
@@ -36,12 +41,17 @@ type Ip = String;
 
 const DIAL_ONLINE_ADDRESSES_INTERVAL: Duration = Duration::from_secs(1);
 const DIAL_ONLINE_ADDRESSES_TOKEN: u64 = 1;
+const SUMMARIZE_PROPAGATION_INTERVAL: Duration = Duration::from_secs(1);
+const SUMMARIZE_PROPAGATION_TOKEN: u64 = 2;
+// Blocks are summarized this long after their first sighting, and forgotten after the retention
+const PROPAGATION_WINDOW: Duration = Duration::from_secs(60);
+const PROPAGATION_RETENTION: Duration = Duration::from_secs(60 * 10);
 
 /// NOTE: CKB full node eviction mechanism only faces to outbound peers. We don't need to care
 /// about the mechanism evict us.
 pub struct CompactBlockCrawler {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<crate::entry::Entry>,
     shared: Arc<RwLock<SharedState>>,
 
     // RPC local_node_info.version
@@ -55,19 +65,24 @@ pub struct CompactBlockCrawler {
     // work at one time.
     compact_blocks: Option<LruCache<packed::Byte32, Ip>>,
 
-    known_ips: HashSet<Ip>,
+    // Shared between RelayProtocol and RelayV2Protocol, since a block may arrive over both from
+    // different peers. Summarized from the SyncProtocol handler only.
+    propagation: Arc<Mutex<PropagationTracker>>,
+
+    enrichment: Enrichment,
 }
 
 impl Clone for CompactBlockCrawler {
     fn clone(&self) -> Self {
         Self {
             node: self.node.clone(),
-            query_sender: self.query_sender.clone(),
+            entry_sender: self.entry_sender.clone(),
             shared: Arc::clone(&self.shared),
             observed_addresses: Arc::clone(&self.observed_addresses),
             client_version: self.client_version.clone(),
             compact_blocks: None,
-            known_ips: HashSet::new(),
+            propagation: Arc::clone(&self.propagation),
+            enrichment: self.enrichment.clone(),
         }
     }
 }
@@ -75,20 +90,25 @@ impl Clone for CompactBlockCrawler {
 impl CompactBlockCrawler {
     pub fn new(
         node: Node,
-        query_sender: crossbeam::channel::Sender<String>,
+        entry_sender: crossbeam::channel::Sender<crate::entry::Entry>,
         shared: Arc<RwLock<SharedState>>,
+        enrichment: Enrichment,
     ) -> Self {
         #[allow(clippy::mutable_key_type)]
-        let bootnodes = bootnodes(CKBNetworkType::Mirana);
+        let bootnodes = bootnodes(CKBNetworkType::from(node.consensus().id.clone()));
         let client_version = node.rpc_client().local_node_info().version;
         Self {
             node,
-            query_sender,
+            entry_sender,
             shared,
             observed_addresses: Arc::new(RwLock::new(bootnodes)),
             client_version,
             compact_blocks: Default::default(),
-            known_ips: Default::default(),
+            propagation: Arc::new(Mutex::new(PropagationTracker::new(
+                PROPAGATION_WINDOW,
+                PROPAGATION_RETENTION,
+            ))),
+            enrichment,
         }
     }
 
@@ -193,6 +213,7 @@ impl CompactBlockCrawler {
                         let ip = addr_to_ip(&context.session.address);
                         self.insert_ipinfo(&ip);
                         self.update_peer_last_compact_block(ip.clone(), &block);
+                        self.record_propagation(&ip, &block);
                         self.insert_compact_block_first_seen(ip, &block);
                     }
                     packed::RelayMessageUnion::RelayTransactionHashes(_) => { /* discard */ }
@@ -249,35 +270,7 @@ impl CompactBlockCrawler {
     }
 
     fn insert_ipinfo(&mut self, ip: &str) {
-        if self.known_ips.contains(ip) {
-            return;
-        }
-
-        if let Ok(ipinfo::IpDetails {
-            ip,
-            country,
-            city,
-            region,
-            company,
-            ..
-        }) = lookup_ipinfo(ip)
-        {
-            let entry = crate::entry::IpInfo {
-                network: self.node.consensus().id.clone(),
-                ip,
-                country,
-                city,
-                region,
-                company: company.map(|company| company.name).unwrap_or_default(),
-            };
-            let raw_query = format!(
-                "INSERT INTO {}.ipinfo(ip, country, city, region, company) \
-                VALUES ('{}', '{}', '{}', '{}', '{}') ON CONFLICT DO NOTHING",
-                entry.network, entry.ip, entry.country, entry.city, entry.region, entry.company,
-            );
-            self.known_ips.insert(entry.ip);
-            self.query_sender.send(raw_query).unwrap();
-        }
+        self.enrichment.enrich(&self.node.consensus().id, ip);
     }
 
     fn insert_compact_block_first_seen(&mut self, ip: Ip, block: &packed::CompactBlock) {
@@ -293,11 +286,58 @@ impl CompactBlockCrawler {
                 block_number,
                 ip,
             };
-            let raw_query = format!(
-                "INSERT INTO {}.compact_block_first_seen(time, block_number, ip) VALUES ('{}', {}, '{}')",
-                entry.network, entry.time, entry.block_number, entry.ip
-            );
-            self.query_sender.send(raw_query).unwrap();
+            self.entry_sender.send(entry.into()).unwrap();
+        }
+    }
+
+    fn record_propagation(&self, ip: &str, block: &packed::CompactBlock) {
+        let header = block.header();
+        let block_number = header.raw().number().unpack();
+        let timestamp = header.raw().timestamp().unpack();
+        let block_hash = header.calc_header_hash();
+        let now = chrono::Utc::now().naive_utc();
+        let sighting = match self.propagation.lock() {
+            Ok(mut propagation) => {
+                propagation.record(block_hash.clone(), block_number, timestamp, ip, now)
+            }
+            Err(_) => return,
+        };
+        if let Some(sighting) = sighting {
+            let entry = crate::entry::BlockPropagationDelay {
+                network: self.node.consensus().id.clone(),
+                time: now,
+                block_number,
+                block_hash,
+                ip: ip.to_string(),
+                delay: sighting.delay,
+                header_delay: sighting.header_delay,
+            };
+            self.entry_sender.send(entry.into()).unwrap();
+        }
+    }
+
+    fn summarize_propagation(&self) {
+        let now = chrono::Utc::now().naive_utc();
+        let summaries = match self.propagation.lock() {
+            Ok(mut propagation) => propagation.drain(now),
+            Err(_) => return,
+        };
+        for summary in summaries {
+            let entry = crate::entry::BlockPropagation {
+                network: self.node.consensus().id.clone(),
+                time: summary.first_seen,
+                block_number: summary.block_number,
+                block_hash: summary.block_hash,
+                first_ip: summary.first_ip,
+                n_peers: summary.n_peers as i32,
+                p50: summary.p50,
+                p90: summary.p90,
+                p99: summary.p99,
+                header_p50: summary.header_p50,
+                header_p90: summary.header_p90,
+                header_p99: summary.header_p99,
+            };
+            self.entry_sender.send(entry.into()).unwrap();
         }
     }
 
@@ -311,14 +351,7 @@ impl CompactBlockCrawler {
             block_hash,
             time: chrono::Utc::now().naive_utc(),
         };
-        let raw_query = format!(
-            "INSERT INTO {}.peer_last_compact_block (ip, block_number, block_hash, time) \
-            VALUES ('{}', {}, '{:#x}', '{}') \
-            ON CONFLICT ( ip ) \
-            DO UPDATE SET (block_number, block_hash, time) = (EXCLUDED.block_number, EXCLUDED.block_hash, EXCLUDED.time)",
-            entry.network, entry.ip, entry.block_number, entry.block_hash, entry.time,
-        );
-        self.query_sender.send(raw_query).unwrap();
+        self.entry_sender.send(entry.into()).unwrap();
     }
 }
 
@@ -332,6 +365,13 @@ impl P2PServiceProtocol for CompactBlockCrawler {
                     DIAL_ONLINE_ADDRESSES_TOKEN,
                 )
                 .unwrap();
+            context
+                .set_service_notify(
+                    SupportProtocols::Sync.protocol_id(),
+                    SUMMARIZE_PROPAGATION_INTERVAL,
+                    SUMMARIZE_PROPAGATION_TOKEN,
+                )
+                .unwrap();
         }
         if context.proto_id == SupportProtocols::Relay.protocol_id()
             || context.proto_id == SupportProtocols::RelayV2.protocol_id()
@@ -363,6 +403,9 @@ impl P2PServiceProtocol for CompactBlockCrawler {
                     }
                 };
             }
+            SUMMARIZE_PROPAGATION_TOKEN => {
+                self.summarize_propagation();
+            }
             _ => unreachable!(),
         }
     }
@@ -7,12 +7,21 @@ use std::time::Duration;
 
 pub struct EpochCrawler {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
+    interval: Duration,
 }
 
 impl EpochCrawler {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(
+        node: Node,
+        entry_sender: crossbeam::channel::Sender<entry::Entry>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            node,
+            entry_sender,
+            interval,
+        }
     }
 
     pub async fn run(&self, last_epoch_number: EpochNumber) {
@@ -20,7 +29,7 @@ impl EpochCrawler {
         let mut tip_epoch = self.node.rpc_client().get_current_epoch();
         loop {
             if current_number >= tip_epoch.number.value() {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+                tokio::time::sleep(self.interval).await;
                 tip_epoch = self.node.rpc_client().get_current_epoch();
                 continue;
             }
@@ -64,19 +73,18 @@ impl EpochCrawler {
                         n_uncles: n_uncles as i32,
                         difficulty: difficulty.to_string(),
                     };
-                    let raw_query = format!(
-                        "INSERT INTO {}.epoch (start_time, end_time, number, length, start_number, n_uncles, difficulty)\
-                             VALUES ('{}', '{}', {}, {}, {}, {}, '{}')",
-                        entry.network,
-                        entry.start_time,
-                        entry.end_time,
-                        entry.number,
-                        entry.length,
-                        entry.start_number,
-                        entry.n_uncles,
-                        entry.difficulty,
-                    );
-                    self.query_sender.send(raw_query).unwrap();
+                    let checkpoint = entry::Checkpoint {
+                        network: self.node.consensus().id.clone(),
+                        time: chrono::Utc::now().naive_utc(),
+                        topic: "EpochCrawler".to_string(),
+                        number: epoch.number.value(),
+                    };
+                    self.entry_sender
+                        .send(entry::Entry::Batch {
+                            records: vec![entry.into(), checkpoint.into()],
+                            backfill: false,
+                        })
+                        .unwrap();
 
                     *current_number += 1;
                 }
@@ -1,5 +1,12 @@
-use crate::util::{bootnodes::bootnodes, ipinfo::lookup_ipinfo, multiaddr::addr_to_ip};
-use ckb_testkit::connector::message::build_discovery_get_nodes;
+use crate::util::{
+    bootnodes::bootnodes,
+    dial_scheduler::DialScheduler,
+    enrichment::Enrichment,
+    latency::{LatencyRecorder, METRIC_CONNECT, METRIC_HANDSHAKE, METRIC_IDENTIFY, METRIC_PING},
+    multiaddr::{addr_to_ip, addr_to_peer_id, PeerAddress},
+    probe::{probe, Probe, ProbeOutcome},
+};
+use ckb_testkit::connector::message::{build_discovery_get_nodes, build_identify_message};
 use ckb_testkit::{
     ckb_types::{packed, prelude::*},
     compress,
@@ -13,7 +20,7 @@ use p2p::{
     context::ProtocolContextMutRef as P2PProtocolContextMutRef,
     context::ServiceContext as P2PServiceContext,
     context::SessionContext,
-    multiaddr::Multiaddr,
+    multiaddr::{Multiaddr, Protocol},
     service::ProtocolHandle as P2PProtocolHandle,
     service::ProtocolMeta as P2PProtocolMeta,
     service::ServiceError as P2PServiceError,
@@ -21,13 +28,15 @@ use p2p::{
     service::TargetProtocol as P2PTargetProtocol,
     traits::ServiceHandle as P2PServiceHandle,
     traits::ServiceProtocol as P2PServiceProtocol,
+    SessionId,
 };
-use rand::{thread_rng, Rng};
-use std::collections::{HashMap, HashSet};
+use futures::StreamExt;
+use lru::LruCache;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::error::Error;
+use std::num::NonZeroUsize;
 use std::ops::Mul;
-use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 use p2p::error::{DialerErrorKind, SendErrorKind};
@@ -37,18 +46,43 @@ use tokio_util::codec::{length_delimited::LengthDelimitedCodec, Decoder, Encoder
 
 // TODO Adjust the parameters
 const DIAL_ONLINE_ADDRESSES_INTERVAL: Duration = Duration::from_secs(1);
-const PRUNE_OFFLINE_ADDRESSES_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+const PRUNE_OFFLINE_ADDRESSES_INTERVAL: Duration = Duration::from_secs(60 * 10);
 
 const DISCONNECT_TIMEOUT_SESSION_INTERVAL: Duration = Duration::from_secs(10);
 const POSTGRES_ONLINE_ADDRESS_INTERVAL: Duration = Duration::from_secs(60);
+const CRAWL_ROUND_INTERVAL: Duration = Duration::from_secs(60 * 10);
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+// Each address is probed again after this, and its latest probe counts as evidence for this long
+const REPROBE_INTERVAL: Duration = Duration::from_secs(60 * 5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 const DIAL_ONLINE_ADDRESSES_TOKEN: u64 = 1;
 const PRUNE_OFFLINE_ADDRESSES_TOKEN: u64 = 2;
 const DISCONNECT_TIMEOUT_SESSION_TOKEN: u64 = 3;
 const POSTGRES_ONLINE_ADDRESSES_TOKEN: u64 = 4;
+const CRAWL_ROUND_TOKEN: u64 = 5;
 
 const ADDRESS_TIMEOUT: Duration = Duration::from_secs(30);
+// Client version advertised in our Identify messages in listening mode
+const ADVERTISED_CLIENT_VERSION: &str = "CKBAnalyzer";
+// Advertised addresses remembered per peer, the least recently advertised ones are evicted
+const MAX_REACHABLE_PER_PEER: usize = 1000;
+// An edge advertised again is sent again only after this, to refresh its `last_seen`
+const EDGE_RESEND_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_ADDRESSES_PER_PEER: usize = 16;
+// Inbound peers queued to be dialed back, the least recently queued ones are dropped
+const MAX_DIALBACKS: usize = 1000;
+
+// Services advertised in the `flag` of Identify, the bitflags of `ckb_network::protocols::identify::Flags`
+const IDENTIFY_FLAGS: &[(u64, &str)] = &[
+    (0b1, "Compatibility"),
+    (0b10, "Discovery"),
+    (0b100, "Sync"),
+    (0b1000, "Relay"),
+    (0b10000, "LightClient"),
+    (0b100000, "BlockFilter"),
+];
 
-#[derive(Copy, Clone, Debug, serde::Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize)]
 pub enum CKBNetworkType {
     Mirana,
     Pudge,
@@ -74,62 +108,213 @@ impl CKBNetworkType {
             CKBNetworkType::Dev => "ckb_dev".to_string(),
         }
     }
+
+    /// Identify name of the nodes of this network, i.e. `/<chain id>/<genesis hash prefix>`.
+    /// Unknown for dev chains, whose genesis varies.
+    pub fn identify_name(&self) -> Option<&'static str> {
+        match self {
+            CKBNetworkType::Mirana => Some("/ckb/92b197aa"),
+            CKBNetworkType::Pudge => Some("/ckb_testnet/10639e08"),
+            CKBNetworkType::Dev => None,
+        }
+    }
 }
 
 /// NetworkCrawler crawl the network reachability info.
 ///
-/// This service opens 2 protocols, Identify and Discovery:
+/// This service opens Identify, Discovery, Ping and Sync, plus LightClient, Filter, Relay, RelayV2
+/// and RelayV3 only to learn which ones each peer negotiates:
 ///
-/// * A ticker to trigger dialing observed addresses
+/// * A ticker to trigger dialing the observed addresses chosen by `self.dial_scheduler`
+/// * A ticker to trigger sending the crawl coverage of the round, and the latency percentiles
+/// of the peers within the round
 /// * A ticker to trigger pruning timeout sessions
-/// * TODO A ticker to trigger pruning offline addresses
+/// * A ticker to trigger pruning the addresses unseen for `prune_window`
+/// * A ticker to trigger sending online peers, and their join/leave events by comparing with
+/// the previous tick
 /// * When opening Identify protocol on a session, reject it if its session type is inbound or
-/// identify name is "CKBAnalyzer", record into `self.online_nodes`.
+/// identify name is "CKBAnalyzer", record into `self.online` by the PeerId authenticated by
+/// the session, so nodes behind one NAT stay apart and one node's addresses are merged.
 /// * When opening Discovery protocol on a session, send `GetNodes` message.
-/// * When receiving inv `Nodes`, record into `self.reachable`
+/// * When opening Ping protocol on a session, send a Ping, and record the round-trip time on
+/// its Pong
+/// * When closing a session, send its Identify payload and the protocols it opened as a
+/// `peer_identify` entry
+/// * When receiving inv `Nodes`, record into `self.observed_addresses`, the sender's
+/// `PeerInfo::reachable`, and send them as `peer_edge` entries
+/// * Aside, `NetworkCrawler::probe_addresses` probes the observed addresses with a TCP
+/// connection and a secio handshake, and sends the outcomes as `address_probe` entries. An
+/// address failing to be dialed is still treated as online if recently probed reachable.
+///
+/// In listening mode, enabled by `NetworkCrawler::listen`, it accepts inbound sessions and
+/// advertises its public address in an Identify message on every session. Inbound peers are not counted online, they
+/// are dialed back at their listen addresses by `dial_back_inbound_peers` and sent as `inbound_peer`
+/// entries.
+///
+/// In snapshot mode, created by `NetworkCrawler::new_snapshot`, it dials every observed
+/// address once starting from bootnodes, i.e. a breadth-first crawl, and sends nothing;
+/// the result is taken by `NetworkCrawler::snapshot_entries`.
 pub struct NetworkCrawler {
     network_type: CKBNetworkType,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<crate::entry::Entry>,
     shared: Arc<RwLock<SharedState>>,
 
     // all observed addresses
-    observed_addresses: Arc<RwLock<HashMap<Multiaddr, usize>>>,
+    observed_addresses: Arc<RwLock<HashMap<Multiaddr, ObservedAddress>>>,
+
+    dial_scheduler: Arc<RwLock<DialScheduler>>,
+
+    // #{ peer id => peer_info }
+    online: Arc<RwLock<HashMap<PeerId, PeerInfo>>>,
+
+    // #{ peer id => join event } of the peers online at the last tick
+    sessions: Arc<RwLock<HashMap<PeerId, crate::entry::PeerSession>>>,
 
-    // #{ ip => peer_info }
-    online: Arc<RwLock<HashMap<Ip, PeerInfo>>>,
+    // #{ session id => identify } of the open sessions
+    session_identifies: Arc<RwLock<HashMap<SessionId, SessionIdentify>>>,
 
-    // Already known iP
-    known_ips: HashSet<String>,
+    // Geolocates the IPs of the online peers
+    enrichment: Enrichment,
 
     // For identify version outside peer protocol context
     observed_version: Arc<RwLock<HashMap<Multiaddr, String>>>,
 
-    // If observed count over this, even peer unable to dial will treat as observed
-    witness_bound: usize,
+    // #{ peer id => Identify name }, to tell the probed nodes of other networks
+    identify_names: Arc<RwLock<HashMap<PeerId, String>>>,
+
+    // Latency samples of the peers within the crawl round
+    latencies: Arc<RwLock<LatencyRecorder>>,
+
+    // Accept inbound sessions, see `NetworkCrawler::listen`
+    listening: bool,
+
+    // Address advertised in our Identify messages
+    public_address: Option<Multiaddr>,
+
+    // Inbound peers to dial back by `dial_back_inbound_peers`, by PeerId in queueing order
+    dialbacks: Arc<RwLock<LruCache<PeerId, Dialback>>>,
+
+    // Addresses unseen for this long are forgotten, except bootnodes
+    prune_window: Duration,
+
+    snapshot: bool,
 }
 
 type Ip = String;
+// Base58 PeerId
+type PeerId = String;
+
+#[derive(Debug, Clone)]
+struct ObservedAddress {
+    last_observed_time: Instant,
+    // The latest probe and its time
+    probed: Option<(Instant, Probe)>,
+}
+
+impl ObservedAddress {
+    fn new() -> Self {
+        Self {
+            last_observed_time: Instant::now(),
+            probed: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionIdentify {
+    identify: Option<crate::entry::PeerIdentify>,
+    // Opened protocol ids
+    protocols: BTreeSet<i32>,
+    opened_at: Option<Instant>,
+    // Nonce and sent time of the pending Ping
+    ping: Option<(u32, Instant)>,
+}
+
+// An inbound peer, and the addresses it may be dialed back at
+struct Dialback {
+    candidates: Vec<Multiaddr>,
+    peer: crate::entry::InboundPeer,
+}
 
 #[derive(Debug, Clone)]
 pub struct PeerInfo {
+    // The latest address
     address: Multiaddr,
+    // All observed addresses, at most `MAX_ADDRESSES_PER_PEER`
+    addresses: HashSet<Multiaddr>,
     last_seen_time: Option<Instant>,
-    reachable: HashSet<Ip>,
+    // #{ advertised ip => last advertised time }
+    reachable: HashMap<Ip, Instant>,
+    // #{ advertised ip => last time its edge was sent }, a subset of `reachable`
+    edges_sent: HashMap<Ip, Instant>,
     client_version: String,
     is_full_node: u8, // 0: unknown, 1: full node, 2: not full node
 }
 
+impl PeerInfo {
+    fn new(address: Multiaddr, is_full_node: u8) -> Self {
+        Self {
+            addresses: std::iter::once(address.clone()).collect(),
+            address,
+            last_seen_time: Default::default(),
+            reachable: Default::default(),
+            edges_sent: Default::default(),
+            client_version: Default::default(),
+            is_full_node,
+        }
+    }
+
+    // Record `address` as the latest one
+    fn observe_address(&mut self, address: &Multiaddr) {
+        self.address = address.clone();
+        self.attach_address(address);
+    }
+
+    fn attach_address(&mut self, address: &Multiaddr) {
+        if self.addresses.len() < MAX_ADDRESSES_PER_PEER {
+            self.addresses.insert(address.clone());
+        }
+    }
+
+    fn ips(&self) -> impl Iterator<Item = Ip> + '_ {
+        self.addresses.iter().map(addr_to_ip)
+    }
+}
+
+// Return the PeerId authenticated by `session`, or the one in its address
+fn session_peer_id(session: &SessionContext) -> Option<PeerId> {
+    session
+        .remote_pubkey
+        .as_ref()
+        .map(|pubkey| pubkey.peer_id().to_base58())
+        .or_else(|| addr_to_peer_id(&session.address))
+}
+
+// Return the ips of the `online` peers
+fn online_ips(online: &HashMap<PeerId, PeerInfo>) -> HashSet<Ip> {
+    online.values().flat_map(PeerInfo::ips).collect()
+}
+
 impl Clone for NetworkCrawler {
     fn clone(&self) -> Self {
         Self {
             network_type: self.network_type.clone(),
-            query_sender: self.query_sender.clone(),
+            entry_sender: self.entry_sender.clone(),
             shared: Arc::clone(&self.shared),
             observed_addresses: Arc::clone(&self.observed_addresses),
+            dial_scheduler: Arc::clone(&self.dial_scheduler),
             online: Arc::clone(&self.online),
-            known_ips: self.known_ips.clone(),
+            sessions: Arc::clone(&self.sessions),
+            session_identifies: Arc::clone(&self.session_identifies),
+            enrichment: self.enrichment.clone(),
             observed_version: self.observed_version.clone(),
-            witness_bound: self.witness_bound.clone(),
+            identify_names: Arc::clone(&self.identify_names),
+            latencies: Arc::clone(&self.latencies),
+            listening: self.listening,
+            public_address: self.public_address.clone(),
+            dialbacks: Arc::clone(&self.dialbacks),
+            prune_window: self.prune_window,
+            snapshot: self.snapshot,
         }
     }
 }
@@ -138,47 +323,150 @@ impl NetworkCrawler {
     /// Create a NetworkCrawler
     pub fn new(
         network_type: CKBNetworkType,
-        query_sender: crossbeam::channel::Sender<String>,
+        entry_sender: crossbeam::channel::Sender<crate::entry::Entry>,
         shared: Arc<RwLock<SharedState>>,
-        witness_bound: usize,
+        enrichment: Enrichment,
+        prune_window: Duration,
+        dial_concurrency: usize,
     ) -> Self {
         #[allow(clippy::mutable_key_type)]
         let bootnodes = bootnodes(network_type);
         let observed_addresses = bootnodes
             .iter()
-            .map(|address| (address.clone(), 1))
+            .map(|address| (address.clone(), ObservedAddress::new()))
             .collect::<HashMap<_, _>>();
+        let mut dial_scheduler = DialScheduler::new(dial_concurrency);
+        for address in bootnodes.iter() {
+            dial_scheduler.add(address.clone());
+        }
         Self {
             network_type,
-            query_sender,
+            entry_sender,
             shared,
             observed_addresses: Arc::new(RwLock::new(observed_addresses.clone())),
+            dial_scheduler: Arc::new(RwLock::new(dial_scheduler)),
             online: Arc::new(RwLock::new(
                 bootnodes
                     .into_iter()
-                    .map(|address| {
-                        (
-                            addr_to_ip(&address),
-                            PeerInfo {
-                                address,
-                                last_seen_time: Default::default(),
-                                reachable: Default::default(),
-                                client_version: Default::default(),
-                                is_full_node: 1,
-                            },
-                        )
+                    .filter_map(|address| {
+                        let peer_id = addr_to_peer_id(&address)?;
+                        Some((peer_id, PeerInfo::new(address, 1)))
                     })
                     .collect(),
             )),
-            known_ips: Default::default(),
+            sessions: Default::default(),
+            session_identifies: Default::default(),
+            enrichment,
             observed_version: Default::default(),
-            witness_bound
+            identify_names: Default::default(),
+            latencies: Default::default(),
+            listening: false,
+            public_address: None,
+            dialbacks: Arc::new(RwLock::new(LruCache::new(NonZeroUsize::new(MAX_DIALBACKS).unwrap()))),
+            prune_window,
+            snapshot: false,
+        }
+    }
+
+    /// Create a NetworkCrawler in snapshot mode
+    pub fn new_snapshot(
+        network_type: CKBNetworkType,
+        entry_sender: crossbeam::channel::Sender<crate::entry::Entry>,
+        shared: Arc<RwLock<SharedState>>,
+        dial_concurrency: usize,
+    ) -> Self {
+        // Snapshots are not geolocated
+        let mut crawler = Self::new(
+            network_type,
+            entry_sender,
+            shared,
+            Enrichment::default(),
+            Duration::MAX,
+            dial_concurrency,
+        );
+        crawler.snapshot = true;
+        if let Ok(mut dial_scheduler) = crawler.dial_scheduler.write() {
+            dial_scheduler.disable_redial();
+        }
+        crawler
+    }
+
+    /// Accept inbound sessions, and record their peers as `inbound_peer` entries after dialing
+    /// them back. With `public_address`, advertise it in our Identify messages, so that nodes
+    /// learn it and dial us.
+    pub fn listen(&mut self, public_address: Option<Multiaddr>) {
+        self.listening = true;
+        self.public_address = public_address;
+    }
+
+    /// Return whether the snapshot crawl is over: nothing left to dial and all sessions closed
+    pub fn is_exhausted(&self) -> bool {
+        let dialed = self
+            .dial_scheduler
+            .read()
+            .map(|dial_scheduler| dial_scheduler.is_exhausted())
+            .unwrap_or(true);
+        let closed = self
+            .shared
+            .read()
+            .map(|shared| shared.get_sessions().is_empty())
+            .unwrap_or(true);
+        dialed && closed
+    }
+
+    /// Return the identified peers and the edges among the advertised ips, tagged with
+    /// `snapshot_id`
+    pub fn snapshot_entries(
+        &self,
+        snapshot_id: i64,
+    ) -> (Vec<crate::entry::SnapshotPeer>, Vec<crate::entry::SnapshotEdge>) {
+        let network = self.network_type.into_legacy_str();
+        let mut peers = Vec::new();
+        let mut edges = Vec::new();
+        if let Ok(online) = self.online.read() {
+            let identified = online
+                .iter()
+                .filter(|(_, peer_info)| peer_info.last_seen_time.is_some())
+                .collect::<HashMap<_, _>>();
+            let identified_ips = identified
+                .values()
+                .flat_map(|peer_info| peer_info.ips())
+                .collect::<HashSet<_>>();
+            for (peer_id, peer_info) in identified.iter() {
+                let ip = addr_to_ip(&peer_info.address);
+                let n_reachable = peer_info
+                    .reachable
+                    .keys()
+                    .filter(|ip1| identified_ips.contains(*ip1))
+                    .count();
+                for dst_ip in peer_info.reachable.keys() {
+                    if *dst_ip != ip {
+                        edges.push(crate::entry::SnapshotEdge {
+                            network: network.clone(),
+                            snapshot_id,
+                            src_ip: ip.clone(),
+                            dst_ip: dst_ip.clone(),
+                        });
+                    }
+                }
+                peers.push(crate::entry::SnapshotPeer {
+                    network: network.clone(),
+                    snapshot_id,
+                    ip,
+                    address: peer_info.address.to_string(),
+                    peer_id: peer_id.to_string(),
+                    version: peer_info.client_version.clone(),
+                    node_type: peer_info.is_full_node,
+                    n_reachable: n_reachable as i32,
+                });
+            }
         }
+        (peers, edges)
     }
 
     /// Convert NetworkCrawler into P2PProtocolMeta
     pub fn build_protocol_metas(&self) -> Vec<P2PProtocolMeta> {
-        vec![
+        let mut metas = vec![
             {
                 let meta_builder: P2PMetaBuilder = SupportProtocols::Identify.into();
                 meta_builder
@@ -191,6 +479,12 @@ impl NetworkCrawler {
                     .service_handle(move || P2PProtocolHandle::Callback(Box::new(self.clone())))
                     .build()
             },
+            {
+                let meta_builder: P2PMetaBuilder = SupportProtocols::Ping.into();
+                meta_builder
+                    .service_handle(move || P2PProtocolHandle::Callback(Box::new(self.clone())))
+                    .build()
+            },
             {
                 // Necessary to communicate with CKB full node
                 let meta_builder: P2PMetaBuilder = SupportProtocols::Sync.into();
@@ -201,7 +495,25 @@ impl NetworkCrawler {
                     .service_handle(move || P2PProtocolHandle::Callback(Box::new(self.clone())))
                     .build()
             },
-        ]
+        ];
+        // Opened only to record which protocols the peers negotiate, their messages are ignored
+        for protocol in [
+            SupportProtocols::LightClient,
+            SupportProtocols::Filter,
+            SupportProtocols::Relay,
+            SupportProtocols::RelayV2,
+            SupportProtocols::RelayV3,
+        ] {
+            let meta_builder: P2PMetaBuilder = protocol.into();
+            metas.push(
+                meta_builder
+                    .before_send(compress)
+                    .before_receive(|| Some(Box::new(decompress)))
+                    .service_handle(move || P2PProtocolHandle::Callback(Box::new(self.clone())))
+                    .build(),
+            );
+        }
+        metas
     }
 
     fn received_identify(&mut self, context: P2PProtocolContextMutRef, data: Bytes) {
@@ -217,6 +529,7 @@ impl NetworkCrawler {
                             String::from_utf8_lossy(&client_version_vec).to_string();
 
                         let client_name_vec: Vec<u8> = identify_payload.name().unpack();
+                        let client_name = String::from_utf8_lossy(&client_name_vec).to_string();
 
                         let client_flag: u64 =  identify_payload.flag().unpack();
 
@@ -229,22 +542,62 @@ impl NetworkCrawler {
                             context.session.address,
                             Instant::now()
                         );
+                        let identify = crate::entry::PeerIdentify {
+                            network: self.network_type.into_legacy_str(),
+                            time: chrono::Utc::now().naive_utc(),
+                            ip: addr_to_ip(&context.session.address),
+                            address: context.session.address.to_string(),
+                            name: client_name.clone(),
+                            client_version: client_version.clone(),
+                            flag: client_flag as i64,
+                            services: decode_identify_flags(client_flag),
+                            listen_addrs: message
+                                .listen_addrs()
+                                .into_iter()
+                                .filter_map(|address| Multiaddr::try_from(address.bytes().raw_data().to_vec()).ok())
+                                .map(|address| address.to_string())
+                                .collect(),
+                            observed_addr: Multiaddr::try_from(message.observed_addr().bytes().raw_data().to_vec())
+                                .map(|address| address.to_string())
+                                .unwrap_or_default(),
+                            protocols: Vec::new(),
+                        };
+                        if context.session.ty.is_inbound() {
+                            self.queue_dialback(context.session, identify, client_flag);
+                            let _ = context.disconnect(context.session.id);
+                            return;
+                        }
+                        let mut opened_at = None;
+                        if let Ok(mut session_identifies) = self.session_identifies.write() {
+                            let session_identify = session_identifies.entry(context.session.id).or_default();
+                            session_identify.identify = Some(identify);
+                            opened_at = session_identify.opened_at;
+                        }
                         if let Ok(mut observed_addresses) = self.observed_addresses.write() {
-                            *observed_addresses.entry(context.session.address.clone()).or_insert(1) = 1;
+                            observed_addresses.insert(context.session.address.clone(), ObservedAddress::new());
                         }
                         if let Ok(mut version_map) = self.observed_version.write() {
                             *version_map.entry(context.session.address.clone()).or_insert(client_version.clone()) = client_version.clone();
                         }
+                        let peer_id = match session_peer_id(context.session) {
+                            Some(peer_id) => peer_id,
+                            None => {
+                                log::warn!("NetworkCrawler received IdentifyMessage without PeerId, address: {}", context.session.address);
+                                return;
+                            }
+                        };
+                        if let Ok(mut identify_names) = self.identify_names.write() {
+                            identify_names.insert(peer_id.clone(), client_name);
+                        }
+                        if let (Some(opened_at), Ok(mut latencies)) = (opened_at, self.latencies.write()) {
+                            let ip = addr_to_ip(&context.session.address);
+                            latencies.record(&peer_id, &ip, METRIC_IDENTIFY, opened_at.elapsed());
+                        }
                         if let (Ok(mut online), client_version) = (self.online.write(), client_version) {
                             let entry = online
-                                .entry(addr_to_ip(&context.session.address))
-                                .or_insert_with(|| PeerInfo {
-                                    address: context.session.address.clone(),
-                                    last_seen_time: Default::default(),
-                                    reachable: Default::default(),
-                                    client_version: Default::default(),
-                                    is_full_node: if is_full_node { 1 } else { 2 },
-                                });
+                                .entry(peer_id)
+                                .or_insert_with(|| PeerInfo::new(context.session.address.clone(), if is_full_node { 1 } else { 2 }));
+                            entry.observe_address(&context.session.address);
                             entry.client_version = client_version;
                             entry.last_seen_time = Some(Instant::now());
                         }
@@ -275,6 +628,8 @@ impl NetworkCrawler {
                             discovery_nodes.items().len(),
                         );
 
+                        let mut advertised = HashSet::new();
+                        let mut witnessed = Vec::new();
                         if let Ok(mut observed_addresses) = self.observed_addresses.write() {
                             for node in discovery_nodes.items() {
                                 for address in node.addresses() {
@@ -285,18 +640,27 @@ impl NetworkCrawler {
                                                 "NetworkCrawler observed new address: {}",
                                                 addr
                                             );
-                                        if let Ok(online) = self.online.write() {
-                                            if online.contains_key(&addr_to_ip(&addr)) &&
-                                                !bootnodes(self.network_type).contains(&addr) {
-                                                match online.get(&addr_to_ip(&addr)).unwrap().last_seen_time {
+                                        advertised.insert(addr_to_ip(&addr));
+                                        if let Some(observed) = observed_addresses.get_mut(&addr) {
+                                            observed.last_observed_time = Instant::now();
+                                        }
+                                        if let Ok(mut online) = self.online.write() {
+                                            let peer_info = addr_to_peer_id(&addr).and_then(|peer_id| online.get_mut(&peer_id));
+                                            if let Some(peer_info) = peer_info.filter(|_| !bootnodes(self.network_type).contains(&addr)) {
+                                                peer_info.attach_address(&addr);
+                                                match peer_info.last_seen_time {
                                                     Some(last_seen_time) => if last_seen_time.elapsed() < Duration::from_secs(60) {
                                                         continue
                                                     },
-                                                    None => { *observed_addresses.entry(addr).or_insert(1) += 1 },
+                                                    None => {
+                                                        observed_addresses.entry(addr.clone()).or_insert_with(ObservedAddress::new);
+                                                        witnessed.push(addr);
+                                                    },
                                                 }
                                             } else {
                                                 // insert default 1 or increment
-                                                *observed_addresses.entry(addr).or_insert(1) += 1;
+                                                observed_addresses.entry(addr.clone()).or_insert_with(ObservedAddress::new);
+                                                witnessed.push(addr);
                                             }
                                         }
                                     }
@@ -304,24 +668,12 @@ impl NetworkCrawler {
                             }
                         }
 
-                        // NOTE: We do not need reachable info now, the commented code is also fixed to avoid unstopped entry adding
-                        //{
-                        //    if let Ok(mut online) = self.online.write() {
-                        //        if online.contains_key(&addr_to_ip(&context.session.address)) {
-                        //            let entry = online.entry(addr_to_ip(&context.session.address)).and_modify(|peer_info|
-                        //                for node in discovery_nodes.items() {
-                        //                    for address in node.addresses() {
-                        //                        if let Ok(addr) =
-                        //                            Multiaddr::try_from(address.raw_data().to_vec())
-                        //                        {
-                        //                            peer_info.reachable.insert(addr_to_ip(&addr));
-                        //                        }
-                        //                    }
-                        //                }
-                        //            );
-                        //        }
-                        //    }
-                        //}
+                        if let Ok(mut dial_scheduler) = self.dial_scheduler.write() {
+                            for addr in witnessed {
+                                dial_scheduler.add(addr);
+                            }
+                        }
+                        self.record_reachable(context.session, advertised);
                     }
                     packed::DiscoveryPayloadUnion::GetNodes(_discovery_get_nodes) => {
                         // discard
@@ -362,37 +714,375 @@ impl NetworkCrawler {
         }
     }
 
-    fn online_witnesses(&mut self, addr: &Multiaddr) {
-        if let Ok(mut observed_addresses) = self.observed_addresses.write() {
-            if let Some(witnesses_count) = observed_addresses.get(&addr) {
-                if witnesses_count >= &self.witness_bound {
-                    log::info!("Failed to dial {:?} but still treat as online because of multiple witnesses. witnesses_count: {}", addr, witnesses_count);
-                    if let Ok(mut online) = self.online.write() {
-                        let entry = online
-                            .entry(addr_to_ip(&addr))
-                            .or_insert_with(|| PeerInfo {
-                                address: addr.clone(),
-                                last_seen_time: Default::default(),
-                                reachable: Default::default(),
-                                client_version: Default::default(),
-                                is_full_node: 0, // can't get this, leave unknown
-                            });
-                        entry.last_seen_time = Some(Instant::now());
-                        if let Ok(version_map) = self.observed_version.read() {
-                            if version_map.contains_key(&addr) {
-                                let version= version_map.get(&addr).unwrap();
-                                entry.client_version = version.clone();
-                            }
+    // Advertise `self.public_address`, if any, in an Identify message
+    fn connected_identify(&mut self, context: P2PProtocolContextMutRef) {
+        let (public_address, name) = match (&self.public_address, self.network_type.identify_name()) {
+            (Some(public_address), Some(name)) => (public_address, name),
+            _ => return,
+        };
+        let message = build_identify_message(
+            name,
+            ADVERTISED_CLIENT_VERSION,
+            vec![public_address.clone()],
+            context.session.address.clone(),
+        );
+        let _ = context.send_message(message.as_bytes());
+    }
+
+    // Queue the inbound peer of `session` for `dial_back_inbound_peers`, replacing its pending
+    // dialback if any
+    fn queue_dialback(&self, session: &SessionContext, identify: crate::entry::PeerIdentify, flag: u64) {
+        let peer_id = match session_peer_id(session) {
+            Some(peer_id) => peer_id,
+            None => return,
+        };
+        let listen_addrs = identify
+            .listen_addrs
+            .iter()
+            .filter_map(|address| address.parse().ok())
+            .collect::<Vec<Multiaddr>>();
+        let dialback = Dialback {
+            candidates: dialback_candidates(&session.address, &listen_addrs),
+            peer: crate::entry::InboundPeer {
+                network: identify.network,
+                time: identify.time,
+                peer_id: peer_id.clone(),
+                ip: identify.ip,
+                address: identify.address,
+                name: identify.name,
+                client_version: identify.client_version,
+                flag: flag as i64,
+                services: identify.services,
+                listen_addrs: identify.listen_addrs,
+                reachable: false,
+                dialback_addr: None,
+            },
+        };
+        if let Ok(mut dialbacks) = self.dialbacks.write() {
+            if let Some((dropped, _)) = dialbacks.push(peer_id.clone(), dialback) {
+                if dropped != peer_id {
+                    log::warn!("NetworkCrawler dropped the dialback of inbound peer {}, too many queued", dropped);
+                }
+            }
+        }
+    }
+
+    // Send a Ping of a random nonce, whose Pong gives the round-trip time of the session
+    fn send_ping(&mut self, context: P2PProtocolContextMutRef) {
+        let nonce: u32 = rand::random();
+        let ping = packed::Ping::new_builder().nonce(nonce.pack()).build();
+        let message = packed::PingMessage::new_builder()
+            .payload(packed::PingPayload::new_builder().set(ping).build())
+            .build();
+        if context.send_message(message.as_bytes()).is_ok() {
+            if let Ok(mut session_identifies) = self.session_identifies.write() {
+                session_identifies.entry(context.session.id).or_default().ping = Some((nonce, Instant::now()));
+            }
+        }
+    }
+
+    fn received_ping(&mut self, context: P2PProtocolContextMutRef, data: Bytes) {
+        let message = match packed::PingMessage::from_compatible_slice(data.as_ref()) {
+            Ok(message) => message,
+            Err(err) => {
+                log::error!("NetworkCrawler received invalid PingMessage, address: {}, error: {:?}", context.session.address, err);
+                return;
+            }
+        };
+        match message.payload().to_enum() {
+            packed::PingPayloadUnion::Ping(ping) => {
+                // Answer, or the node may close the session
+                let pong = packed::Pong::new_builder().nonce(ping.nonce()).build();
+                let message = packed::PingMessage::new_builder()
+                    .payload(packed::PingPayload::new_builder().set(pong).build())
+                    .build();
+                let _ = context.send_message(message.as_bytes());
+            }
+            packed::PingPayloadUnion::Pong(pong) => {
+                let nonce: u32 = pong.nonce().unpack();
+                let sent = self.session_identifies.write().ok().and_then(|mut session_identifies| {
+                    let session_identify = session_identifies.get_mut(&context.session.id)?;
+                    match session_identify.ping {
+                        Some((sent_nonce, sent_time)) if sent_nonce == nonce => {
+                            session_identify.ping = None;
+                            Some(sent_time)
                         }
-                        // Reset witness count
-                        observed_addresses.entry(addr.to_owned()).and_modify( |cnt|
-                            *cnt = 0
-                        );
+                        _ => None,
+                    }
+                });
+                if let (Some(sent_time), Some(peer_id)) = (sent, session_peer_id(context.session)) {
+                    if let Ok(mut latencies) = self.latencies.write() {
+                        let ip = addr_to_ip(&context.session.address);
+                        latencies.record(&peer_id, &ip, METRIC_PING, sent_time.elapsed());
                     }
                 }
             }
         }
     }
+
+    /// Treat `addr` as online despite a failed dial if its latest probe, within
+    /// `REPROBE_INTERVAL`, found it reachable, e.g. the node accepts connections but is full
+    fn online_by_probe(&mut self, addr: &Multiaddr) {
+        let peer_id = match self.observed_addresses.read() {
+            Ok(observed_addresses) => match observed_addresses.get(addr).and_then(|observed| observed.probed.as_ref()) {
+                Some((probed_time, Probe { outcome: ProbeOutcome::Reachable, peer_id: Some(peer_id), .. }))
+                    if probed_time.elapsed() <= REPROBE_INTERVAL => peer_id.clone(),
+                _ => return,
+            },
+            Err(_) => return,
+        };
+        log::info!("Failed to dial {:?} but still treat as online because it was probed reachable", addr);
+        if let Ok(mut online) = self.online.write() {
+            // can't get node type, leave unknown
+            let entry = online
+                .entry(peer_id)
+                .or_insert_with(|| PeerInfo::new(addr.clone(), 0));
+            entry.observe_address(addr);
+            entry.last_seen_time = Some(Instant::now());
+            if let Ok(version_map) = self.observed_version.read() {
+                if let Some(version) = version_map.get(addr) {
+                    entry.client_version = version.clone();
+                }
+            }
+        }
+    }
+
+    /// Every `PROBE_INTERVAL`, probe the observed addresses not probed within
+    /// `REPROBE_INTERVAL`, at most `concurrency` at a time, and send the outcomes. Runs until the
+    /// process exits, not in snapshot mode.
+    pub async fn probe_addresses(self, concurrency: usize) {
+        let mut interval = tokio::time::interval(PROBE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let addresses = match self.observed_addresses.read() {
+                Ok(observed_addresses) => observed_addresses
+                    .iter()
+                    .filter(|(address, observed)| {
+                        PeerAddress::from(*address).is_dialable()
+                            && observed.probed.as_ref().map_or(true, |(probed_time, _)| {
+                                probed_time.elapsed() > REPROBE_INTERVAL
+                            })
+                    })
+                    .map(|(address, _)| address.clone())
+                    .collect::<Vec<_>>(),
+                Err(_) => continue,
+            };
+            let mut probes = futures::stream::iter(addresses)
+                .map(|address| async move {
+                    let probed = probe(&address, PROBE_TIMEOUT).await;
+                    (address, probed)
+                })
+                .buffer_unordered(concurrency);
+            while let Some((address, probed)) = probes.next().await {
+                self.probed(address, probed);
+            }
+        }
+    }
+
+    // Classify the network of a reachable address by the Identify name its PeerId sent before,
+    // and send the outcome
+    fn probed(&self, address: Multiaddr, mut probed: Probe) {
+        if probed.outcome == ProbeOutcome::Reachable {
+            let identify_name = probed.peer_id.as_ref().and_then(|peer_id| {
+                self.identify_names.read().ok()?.get(peer_id).cloned()
+            });
+            if let (Some(identify_name), Some(expected)) = (identify_name, self.network_type.identify_name()) {
+                if identify_name != expected {
+                    probed.outcome = ProbeOutcome::WrongNetwork;
+                }
+            }
+        }
+        log::debug!("NetworkCrawler probed {}, {:?}", address, probed);
+        if let (Some(peer_id), Ok(mut latencies)) = (probed.peer_id.as_ref(), self.latencies.write()) {
+            let ip = addr_to_ip(&address);
+            if let Some(latency) = probed.latency {
+                latencies.record(peer_id, &ip, METRIC_CONNECT, latency);
+            }
+            if let Some(handshake) = probed.handshake {
+                latencies.record(peer_id, &ip, METRIC_HANDSHAKE, handshake);
+            }
+        }
+        let entry = crate::entry::AddressProbe {
+            network: self.network_type.into_legacy_str(),
+            time: chrono::Utc::now().naive_utc(),
+            ip: addr_to_ip(&address),
+            address: address.to_string(),
+            peer_id: probed.peer_id.clone(),
+            outcome: probed.outcome.as_str().to_string(),
+            latency: probed.latency.map(|latency| latency.as_millis() as i32),
+        };
+        self.entry_sender.send(entry.into()).unwrap();
+        if let Ok(mut observed_addresses) = self.observed_addresses.write() {
+            if let Some(observed) = observed_addresses.get_mut(&address) {
+                observed.probed = Some((Instant::now(), probed));
+            }
+        }
+    }
+
+    /// Every `PROBE_INTERVAL`, dial back the queued inbound peers, at most `concurrency` at a
+    /// time, and send them. Runs until the process exits, only when listening.
+    pub async fn dial_back_inbound_peers(self, concurrency: usize) {
+        let mut interval = tokio::time::interval(PROBE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let dialbacks = match self.dialbacks.write() {
+                Ok(mut dialbacks) => {
+                    let n = dialbacks.len().min(concurrency);
+                    (0..n).filter_map(|_| dialbacks.pop_lru().map(|(_, dialback)| dialback)).collect::<Vec<_>>()
+                }
+                Err(_) => continue,
+            };
+            for peer in futures::future::join_all(dialbacks.into_iter().map(dial_back)).await {
+                log::debug!("NetworkCrawler dialed back inbound peer {:?}", peer);
+                self.entry_sender.send(peer.into()).unwrap();
+            }
+        }
+    }
+
+    /// Record the ips advertised by the peer of `session` into its `PeerInfo::reachable`, and
+    /// send the edges new to it or not sent within `EDGE_RESEND_INTERVAL`
+    fn record_reachable(&self, session: &SessionContext, advertised: HashSet<Ip>) {
+        let src_ip = addr_to_ip(&session.address);
+        let now = Instant::now();
+        let mut unsent = Vec::new();
+        if let (Ok(mut online), Some(peer_id)) = (self.online.write(), session_peer_id(session)) {
+            if let Some(peer_info) = online.get_mut(&peer_id) {
+                for dst_ip in advertised.iter() {
+                    peer_info.reachable.insert(dst_ip.clone(), now);
+                }
+                if peer_info.reachable.len() > MAX_REACHABLE_PER_PEER {
+                    let mut reachable = peer_info.reachable.drain().collect::<Vec<_>>();
+                    reachable.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+                    reachable.truncate(MAX_REACHABLE_PER_PEER);
+                    peer_info.reachable = reachable.into_iter().collect();
+                }
+                let PeerInfo { reachable, edges_sent, .. } = peer_info;
+                edges_sent.retain(|ip, _| reachable.contains_key(ip));
+                for dst_ip in advertised {
+                    if dst_ip == src_ip || !reachable.contains_key(&dst_ip) {
+                        continue;
+                    }
+                    let due = edges_sent
+                        .get(&dst_ip)
+                        .map_or(true, |sent_time| now.duration_since(*sent_time) >= EDGE_RESEND_INTERVAL);
+                    if due {
+                        edges_sent.insert(dst_ip.clone(), now);
+                        unsent.push(dst_ip);
+                    }
+                }
+            }
+        }
+        if self.snapshot {
+            return;
+        }
+
+        let time = chrono::Utc::now().naive_utc();
+        for dst_ip in unsent {
+            let entry = crate::entry::PeerEdge {
+                network: self.network_type.into_legacy_str(),
+                time,
+                src_ip: src_ip.clone(),
+                dst_ip,
+            };
+            self.entry_sender.send(entry.into()).unwrap();
+        }
+    }
+
+    /// Forget the addresses neither observed nor seen online within `prune_window`, except
+    /// bootnodes
+    fn prune_offline_addresses(&mut self) {
+        #[allow(clippy::mutable_key_type)]
+        let bootnodes = bootnodes(self.network_type);
+        let prune_window = self.prune_window;
+        let mut pruned_addresses = Vec::new();
+        if let Ok(mut observed_addresses) = self.observed_addresses.write() {
+            observed_addresses.retain(|address, observed| {
+                let keep = bootnodes.contains(address)
+                    || observed.last_observed_time.elapsed() <= prune_window;
+                if !keep {
+                    pruned_addresses.push(address.clone());
+                }
+                keep
+            });
+            if let Ok(mut version_map) = self.observed_version.write() {
+                version_map.retain(|address, _| observed_addresses.contains_key(address));
+            }
+        }
+        if let Ok(mut dial_scheduler) = self.dial_scheduler.write() {
+            for address in pruned_addresses.iter() {
+                dial_scheduler.remove(address);
+            }
+        }
+
+        let mut pruned_ips = HashSet::new();
+        if let Ok(mut online) = self.online.write() {
+            for peer_info in online.values_mut() {
+                let PeerInfo { reachable, edges_sent, .. } = peer_info;
+                reachable.retain(|_, last_advertised_time| last_advertised_time.elapsed() <= prune_window);
+                edges_sent.retain(|ip, _| reachable.contains_key(ip));
+            }
+            online.retain(|_, peer_info| {
+                let seen = peer_info
+                    .last_seen_time
+                    .map(|last_seen_time| last_seen_time.elapsed() <= prune_window)
+                    .unwrap_or(false);
+                if seen || bootnodes.contains(&peer_info.address) {
+                    true
+                } else {
+                    pruned_ips.extend(peer_info.ips());
+                    false
+                }
+            });
+        }
+        log::info!(
+            "NetworkCrawler pruned {} addresses and {} peers unseen for {:?}",
+            pruned_addresses.len(),
+            pruned_ips.len(),
+            prune_window
+        );
+    }
+
+    /// Compare the online `peers` with the ones of the last tick, return the join events of
+    /// the new peers and the leave events of the gone ones
+    fn track_sessions(
+        &self,
+        now: chrono::NaiveDateTime,
+        peers: &[crate::entry::Peer],
+    ) -> Vec<crate::entry::PeerSession> {
+        let mut events = Vec::new();
+        if let Ok(mut sessions) = self.sessions.write() {
+            let online_peer_ids = peers.iter().map(|peer| peer.peer_id.as_str()).collect::<HashSet<_>>();
+            sessions.retain(|peer_id, joined| {
+                if online_peer_ids.contains(peer_id.as_str()) {
+                    return true;
+                }
+                events.push(crate::entry::PeerSession {
+                    time: now,
+                    event: "leave".to_string(),
+                    uptime: Some((now - joined.joined_at).num_seconds()),
+                    ..joined.clone()
+                });
+                false
+            });
+            for peer in peers {
+                if !sessions.contains_key(&peer.peer_id) {
+                    let joined = crate::entry::PeerSession {
+                        network: peer.network.clone(),
+                        time: now,
+                        event: "join".to_string(),
+                        ip: peer.ip.clone(),
+                        address: peer.address.clone(),
+                        peer_id: peer.peer_id.clone(),
+                        joined_at: now,
+                        uptime: None,
+                    };
+                    sessions.insert(peer.peer_id.clone(), joined.clone());
+                    events.push(joined);
+                }
+            }
+        }
+        events
+    }
 }
 
 impl P2PServiceProtocol for NetworkCrawler {
@@ -408,15 +1098,19 @@ impl P2PServiceProtocol for NetworkCrawler {
             context
                 .set_service_notify(
                     SupportProtocols::Sync.protocol_id(),
-                    PRUNE_OFFLINE_ADDRESSES_INTERVAL,
-                    PRUNE_OFFLINE_ADDRESSES_TOKEN,
+                    DISCONNECT_TIMEOUT_SESSION_INTERVAL,
+                    DISCONNECT_TIMEOUT_SESSION_TOKEN,
                 )
                 .unwrap();
+            // Snapshot mode sends nothing until the crawl is over, nor forgets anything
+            if self.snapshot {
+                return;
+            }
             context
                 .set_service_notify(
                     SupportProtocols::Sync.protocol_id(),
-                    DISCONNECT_TIMEOUT_SESSION_INTERVAL,
-                    DISCONNECT_TIMEOUT_SESSION_TOKEN,
+                    PRUNE_OFFLINE_ADDRESSES_INTERVAL,
+                    PRUNE_OFFLINE_ADDRESSES_TOKEN,
                 )
                 .unwrap();
             context
@@ -426,44 +1120,89 @@ impl P2PServiceProtocol for NetworkCrawler {
                     POSTGRES_ONLINE_ADDRESSES_TOKEN,
                 )
                 .unwrap();
+            context
+                .set_service_notify(
+                    SupportProtocols::Sync.protocol_id(),
+                    CRAWL_ROUND_INTERVAL,
+                    CRAWL_ROUND_TOKEN,
+                )
+                .unwrap();
         }
     }
 
     fn notify(&mut self, context: &mut P2PProtocolContext, token: u64) {
         match token {
             DIAL_ONLINE_ADDRESSES_TOKEN => {
-                // context.remove_service_notify();
-                // context.set_service_notify();
-                if let Ok(observed_addresses) = self.observed_addresses.read() {
-                    if observed_addresses.is_empty() {
-                        return
+                let now = Instant::now();
+                let addresses = self
+                    .dial_scheduler
+                    .write()
+                    .map(|mut dial_scheduler| dial_scheduler.next(now))
+                    .unwrap_or_default();
+                for address in addresses {
+                    let connected = self.shared.read().unwrap().get_session(&address).is_some();
+                    if connected {
+                        if let Ok(mut dial_scheduler) = self.dial_scheduler.write() {
+                            dial_scheduler.succeeded(&address, now);
+                        }
+                        continue;
                     }
-                }
-                let mut rng = thread_rng();
-                let mut dial_res = None;
-                let mut addr = None;
-                if let Ok(mut observed_addresses) = self.observed_addresses.write() {
-                    let random_index = rng.gen_range(0..observed_addresses.len());
-                    let random_address = observed_addresses
-                        .iter()
-                        .collect::<Vec<_>>()
-                        .get(random_index)
-                        .cloned()
-                        .unwrap();
-                    if self
-                        .shared
-                        .read()
-                        .unwrap()
-                        .get_session(random_address.0)
-                        .is_none()
-                    {
-                        log::debug!("Try dial {}", random_address.0.clone());
-                        dial_res = Some(context.dial(random_address.0.clone(), P2PTargetProtocol::All));
-                        addr = Some(random_address.0.clone());
+                    log::debug!("Try dial {}", address);
+                    if context.dial(address.clone(), P2PTargetProtocol::All).is_err() {
+                        if let Ok(mut dial_scheduler) = self.dial_scheduler.write() {
+                            dial_scheduler.failed(&address, now);
+                        }
+                        self.online_by_probe(&address);
                     }
+                }
+            }
+            CRAWL_ROUND_TOKEN => {
+                let stats = match self.dial_scheduler.write() {
+                    Ok(mut dial_scheduler) => dial_scheduler.finish_round(),
+                    Err(_) => return,
+                };
+                log::info!("NetworkCrawler finished crawl round, {:?}", stats);
+                let entry = crate::entry::CrawlRound {
+                    network: self.network_type.into_legacy_str(),
+                    time: chrono::Utc::now().naive_utc(),
+                    n_known: stats.n_known as i32,
+                    n_dialed: stats.n_dialed as i32,
+                    n_succeeded: stats.n_succeeded as i32,
+                    n_failures: stats.n_failures as i32,
+                    n_never_dialed: stats.n_never_dialed as i32,
+                    n_backing_off: stats.n_backing_off as i32,
+                };
+                self.entry_sender.send(entry.into()).unwrap();
+                for (transport, transport_stats) in stats.transports {
+                    let entry = crate::entry::TransportReachability {
+                        network: self.network_type.into_legacy_str(),
+                        time: chrono::Utc::now().naive_utc(),
+                        transport: transport.to_string(),
+                        n_known: transport_stats.n_known as i32,
+                        n_dialed: transport_stats.n_dialed as i32,
+                        n_succeeded: transport_stats.n_succeeded as i32,
+                    };
+                    self.entry_sender.send(entry.into()).unwrap();
+                }
+
+                let summaries = match self.latencies.write() {
+                    Ok(mut latencies) => latencies.drain(),
+                    Err(_) => return,
                 };
-                if let Some(Err(_)) = dial_res {
-                    self.online_witnesses(&addr.unwrap());
+                let millis = |latency: Duration| latency.as_secs_f64() * 1000.0;
+                for summary in summaries {
+                    let entry = crate::entry::PeerLatency {
+                        network: self.network_type.into_legacy_str(),
+                        time: chrono::Utc::now().naive_utc(),
+                        peer_id: summary.peer_id,
+                        ip: summary.ip,
+                        metric: summary.metric.to_string(),
+                        n_samples: summary.n_samples as i32,
+                        p50: millis(summary.p50),
+                        p90: millis(summary.p90),
+                        p99: millis(summary.p99),
+                    };
+                    self.entry_sender.send(entry.into()).unwrap();
                 }
             }
             DISCONNECT_TIMEOUT_SESSION_TOKEN => {
@@ -481,7 +1220,7 @@ impl P2PServiceProtocol for NetworkCrawler {
                 };
                 if let Ok(online) = self.online.read() {
                     for session in sessions {
-                        if let Some(peer_info) = online.get(&addr_to_ip(&session.address)) {
+                        if let Some(peer_info) = session_peer_id(&session).and_then(|peer_id| online.get(&peer_id)) {
                             if let Some(last_seen_time) = peer_info.last_seen_time {
                                 if last_seen_time.elapsed() > Duration::from_secs(10) {
                                     let _ = context.disconnect(session.id);
@@ -495,25 +1234,27 @@ impl P2PServiceProtocol for NetworkCrawler {
                 let now = chrono::Utc::now().naive_utc();
                 let mut entries = Vec::new();
                 if let Ok(online) = self.online.read() {
-                    for (ip, peer_info) in online.iter() {
+                    let online_ips = online_ips(&online);
+                    for (peer_id, peer_info) in online.iter() {
                         if let Some(last_seen_time) = peer_info.last_seen_time {
                             if last_seen_time.elapsed() <= ADDRESS_TIMEOUT {
                                 // It's a online address
                                 let n_reachable = {
                                     peer_info
                                         .reachable
-                                        .iter()
-                                        .filter(|ip1| online.contains_key(*ip1))
+                                        .keys()
+                                        .filter(|ip1| online_ips.contains(*ip1))
                                         .count()
                                 };
                                 let entry = crate::entry::Peer {
                                     network: self.network_type.into_legacy_str(),
                                     time: now,
                                     version: peer_info.client_version.clone(),
-                                    ip: ip.clone(),
+                                    ip: addr_to_ip(&peer_info.address),
                                     n_reachable: n_reachable as i32,
-                                    address: peer_info.address.to_string().clone(),
-                                    peer_id: peer_info.address.to_string().split('/').collect::<Vec<&str>>().last().unwrap_or(&"").to_string(),
+                                    address: peer_info.address.to_string(),
+                                    addresses: peer_info.addresses.iter().map(|address| address.to_string()).collect(),
+                                    peer_id: peer_id.clone(),
                                     node_type: peer_info.is_full_node,
                                 };
                                 entries.push(entry);
@@ -523,74 +1264,18 @@ impl P2PServiceProtocol for NetworkCrawler {
                 }
 
                 for entry in entries.iter() {
-                    let raw_query = format!(
-                        "INSERT INTO {}.peer(time, version, ip, n_reachable, address, peer_id, node_type) \
-                        VALUES ('{}', '{}', '{}', {}, '{}', '{}', {}) \
-                        ON CONFLICT (address) DO UPDATE SET time = excluded.time, n_reachable = excluded.n_reachable",
-                        entry.network, entry.time, entry.version, entry.ip, entry.n_reachable, entry.address, entry.peer_id, entry.node_type,
-                    );
-                    self.query_sender.send(raw_query).unwrap();
+                    self.entry_sender.send(entry.clone().into()).unwrap();
+                }
+                for event in self.track_sessions(now, &entries) {
+                    self.entry_sender.send(event.into()).unwrap();
                 }
 
                 for entry in entries {
-                    if !self.known_ips.contains(&entry.ip) {
-                        if let Ok(ipinfo::IpDetails {
-                            ip,
-                            country,
-                            city,
-                            region,
-                            company,
-                            loc,
-                            ..
-                        }) = lookup_ipinfo(&entry.ip)
-                        {
-                            let entry = crate::entry::IpInfo {
-                                network: entry.network,
-                                ip,
-                                country: country.replace("'", "''"),
-                                city: city.replace("'", "''"),
-                                region,
-                                company: company.map(|company| company.name).unwrap_or_default().replace("'", "''"),
-                            };
-
-                            let mut lat_lon = loc.split(',');
-                            // Parse each part to f64, providing a default if the value can't be parsed
-                            let latitude: f64 = lat_lon.next().and_then(|s| f64::from_str(s).ok()).unwrap_or_default();
-                            let longitude: f64 = lat_lon.next().and_then(|s| f64::from_str(s).ok()).unwrap_or_default();
-
-                            let raw_query = format!(
-                                "INSERT INTO {}.ipinfo(ip, country, city, region, company, latitude, longitude) \
-                                VALUES ('{}', '{}', '{}', '{}', '{}', '{}', '{}') ON CONFLICT DO NOTHING",
-                                entry.network,
-                                entry.ip,
-                                entry.country,
-                                entry.city,
-                                entry.region,
-                                entry.company,
-                                latitude,
-                                longitude,
-                            );
-                            self.known_ips.insert(entry.ip);
-                            self.query_sender.send(raw_query).unwrap();
-
-                            let query = format!("INSERT INTO common_info.lat_info (city, country, state1, latitude, longitude)
-                            VALUES ({}, {}, {}, {}, {}) ON CONFLICT (city, country) DO NOTHING", entry.city, entry.country, entry.region, latitude, longitude);
-
-                        } else {
-                            log::warn!("Failed to lookup ipinfo for {}", entry.ip);
-                        }
-                    }
+                    self.enrichment.enrich(&entry.network, &entry.ip);
                 }
             }
             PRUNE_OFFLINE_ADDRESSES_TOKEN => {
-                // TODO: prune offline addresses
-                // if let Ok(mut online) = self.online.write() {
-                //     log::warn!("Removing offline addresses!!");
-                //     online.retain(|&_, peer| { // remove offline addresses
-                //         !peer.client_version.is_empty() || peer.last_seen_time.unwrap().elapsed() <= ADDRESS_TIMEOUT
-                //     });
-                // }
-
+                self.prune_offline_addresses();
             }
             _ => unreachable!(),
         }
@@ -609,9 +1294,20 @@ impl P2PServiceProtocol for NetworkCrawler {
         if let Ok(mut shared) = self.shared.write() {
             shared.add_protocol(context.session, context.proto_id);
         }
+        if let Ok(mut session_identifies) = self.session_identifies.write() {
+            session_identifies
+                .entry(context.session.id)
+                .or_default()
+                .protocols
+                .insert(context.proto_id.value() as i32);
+        }
 
         if context.proto_id() == SupportProtocols::Discovery.protocol_id() {
             self.connected_discovery(context, protocol_version)
+        } else if context.proto_id() == SupportProtocols::Identify.protocol_id() {
+            self.connected_identify(context)
+        } else if context.proto_id() == SupportProtocols::Ping.protocol_id() {
+            self.send_ping(context)
         }
     }
 
@@ -635,6 +1331,8 @@ impl P2PServiceProtocol for NetworkCrawler {
             self.received_discovery(context, data)
         } else if context.proto_id == SupportProtocols::Identify.protocol_id() {
             self.received_identify(context, data)
+        } else if context.proto_id == SupportProtocols::Ping.protocol_id() {
+            self.received_ping(context, data)
         }
     }
 }
@@ -643,7 +1341,10 @@ impl P2PServiceHandle for NetworkCrawler {
     fn handle_error(&mut self, _context: &mut P2PServiceContext, error: P2PServiceError) {
         match &error {
             P2PServiceError::DialerError { address, error }  => {
-                self.online_witnesses(address);
+                if let Ok(mut dial_scheduler) = self.dial_scheduler.write() {
+                    dial_scheduler.failed(address, Instant::now());
+                }
+                self.online_by_probe(address);
             },
             P2PServiceError::ProtocolSelectError { .. } => {
                 // discard
@@ -661,10 +1362,17 @@ impl P2PServiceHandle for NetworkCrawler {
                 session_context: session,
             } => {
                 log::debug!("NetworkCrawler open session: {:?}", session);
-                // Reject passive connection
+                // Reject passive connection, unless listening
                 if session.ty.is_inbound() {
-                    let _ = context.disconnect(session.id);
-                    return;
+                    if !self.listening {
+                        let _ = context.disconnect(session.id);
+                        return;
+                    }
+                } else if let Ok(mut dial_scheduler) = self.dial_scheduler.write() {
+                    dial_scheduler.succeeded(&session.address, Instant::now());
+                }
+                if let Ok(mut session_identifies) = self.session_identifies.write() {
+                    session_identifies.entry(session.id).or_default().opened_at = Some(Instant::now());
                 }
 
                 let _add = self
@@ -680,6 +1388,17 @@ impl P2PServiceHandle for NetworkCrawler {
                     .shared
                     .write()
                     .map(|mut shared| shared.remove_session(&session.id));
+                let session_identify = self
+                    .session_identifies
+                    .write()
+                    .ok()
+                    .and_then(|mut session_identifies| session_identifies.remove(&session.id));
+                if let Some(SessionIdentify { identify: Some(mut identify), protocols, .. }) = session_identify {
+                    if !self.snapshot {
+                        identify.protocols = protocols.into_iter().collect();
+                        self.entry_sender.send(identify.into()).unwrap();
+                    }
+                }
             }
             _ => {
                 unimplemented!()
@@ -687,3 +1406,80 @@ impl P2PServiceHandle for NetworkCrawler {
         }
     }
 }
+
+// Probe the candidates of an inbound peer in turn, until one reaches the same PeerId
+async fn dial_back(dialback: Dialback) -> crate::entry::InboundPeer {
+    let Dialback { candidates, mut peer } = dialback;
+    for candidate in candidates {
+        let probed = probe(&candidate, PROBE_TIMEOUT).await;
+        if probed.outcome == ProbeOutcome::Reachable && probed.peer_id.as_ref() == Some(&peer.peer_id) {
+            peer.reachable = true;
+            peer.dialback_addr = Some(candidate.to_string());
+            break;
+        }
+    }
+    peer
+}
+
+// Addresses to dial an inbound peer back at: its public listen addresses, and the ports of all
+// its listen addresses at the IP it connected from, since NATed nodes usually listen on private
+// ones
+fn dialback_candidates(remote: &Multiaddr, listen_addrs: &[Multiaddr]) -> Vec<Multiaddr> {
+    let mut candidates = Vec::new();
+    for listen_addr in listen_addrs {
+        if PeerAddress::from(listen_addr).is_public() {
+            candidates.push(listen_addr.clone());
+        }
+        let port = listen_addr.iter().find_map(|protocol| match protocol {
+            Protocol::Tcp(port) => Some(port),
+            _ => None,
+        });
+        if let (Some(host), Some(port)) = (remote.iter().next(), port) {
+            let mut candidate: Multiaddr = std::iter::once(host).collect();
+            candidate.push(Protocol::Tcp(port));
+            candidates.push(candidate);
+        }
+    }
+    let mut seen = HashSet::new();
+    candidates.retain(|candidate| seen.insert(candidate.clone()));
+    candidates
+}
+
+// Return the names of the services set in an Identify `flag`, unknown bits in hex
+fn decode_identify_flags(flag: u64) -> Vec<String> {
+    (0..64)
+        .map(|i| 1u64 << i)
+        .filter(|bit| flag & bit != 0)
+        .map(|bit| {
+            IDENTIFY_FLAGS
+                .iter()
+                .find(|(known, _)| *known == bit)
+                .map(|(_, name)| name.to_string())
+                .unwrap_or_else(|| format!("{:#x}", bit))
+        })
+        .collect()
+}
+
+#[test]
+fn test_decode_identify_flags() {
+    assert!(decode_identify_flags(0).is_empty());
+    assert_eq!(
+        decode_identify_flags(0b11111),
+        vec!["Compatibility", "Discovery", "Sync", "Relay", "LightClient"]
+    );
+    assert_eq!(decode_identify_flags(0b1000100), vec!["Sync", "0x40"]);
+}
+
+#[test]
+fn test_dialback_candidates() {
+    let addr = |addr: &str| -> Multiaddr { addr.parse().unwrap() };
+    let remote = addr("/ip4/1.2.3.4/tcp/53012");
+    assert_eq!(
+        dialback_candidates(
+            &remote,
+            &[addr("/ip4/192.168.1.2/tcp/8115"), addr("/ip4/5.6.7.8/tcp/8116"), addr("/ip4/0.0.0.0/tcp/8115")]
+        ),
+        vec![addr("/ip4/1.2.3.4/tcp/8115"), addr("/ip4/5.6.7.8/tcp/8116"), addr("/ip4/1.2.3.4/tcp/8116")]
+    );
+    assert!(dialback_candidates(&remote, &[]).is_empty());
+}
@@ -4,12 +4,21 @@ use std::time::Duration;
 
 pub struct PoolCrawler {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
+    interval: Duration,
 }
 
 impl PoolCrawler {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(
+        node: Node,
+        entry_sender: crossbeam::channel::Sender<entry::Entry>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            node,
+            entry_sender,
+            interval,
+        }
     }
 
     pub async fn run(&self) {
@@ -24,14 +33,9 @@ impl PoolCrawler {
                 proposed: tx_pool_info.proposed.value() as i64,
                 orphan: tx_pool_info.orphan.value() as i64,
             };
-            let raw_query = format!(
-                "INSERT INTO {}.tx_pool_info(time, total_tx_cycles, total_tx_size, pending, proposed, orphan) \
-            VALUES ('{}', {}, {}, {}, {}, {})",
-                entry.network, entry.time, entry.total_tx_cycles, entry.total_tx_size, entry.pending, entry.proposed, entry.orphan
-            );
-            self.query_sender.send(raw_query).unwrap();
+            self.entry_sender.send(entry.into()).unwrap();
 
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            tokio::time::sleep(self.interval).await;
         }
     }
 }
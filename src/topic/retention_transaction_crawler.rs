@@ -7,12 +7,21 @@ use std::time::Duration;
 
 pub struct RetentionTransactionCrawler {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
+    interval: Duration,
 }
 
 impl RetentionTransactionCrawler {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(
+        node: Node,
+        entry_sender: crossbeam::channel::Sender<entry::Entry>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            node,
+            entry_sender,
+            interval,
+        }
     }
 
     pub async fn run(&self) {
@@ -33,11 +42,7 @@ impl RetentionTransactionCrawler {
                                 time: now,
                                 hash: hash.pack(),
                             };
-                            let raw_query = format!(
-                                    "INSERT INTO {}.retention_transaction (time, hash) VALUES ('{}', '{:#x}')",
-                                    entry.network, entry.time, entry.hash
-                                );
-                            self.query_sender.send(raw_query).unwrap();
+                            self.entry_sender.send(entry.into()).unwrap();
                         }
 
                         last_observation_pool = in_pool;
@@ -48,7 +53,7 @@ impl RetentionTransactionCrawler {
                 },
             }
 
-            tokio::time::sleep(Duration::from_secs(60 * 10)).await;
+            tokio::time::sleep(self.interval).await;
         }
     }
 }
@@ -6,12 +6,12 @@ use tokio::net::ToSocketAddrs;
 
 pub struct SubscribeProposedTransaction {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
 }
 
 impl SubscribeProposedTransaction {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(node: Node, entry_sender: crossbeam::channel::Sender<entry::Entry>) -> Self {
+        Self { node, entry_sender }
     }
 
     pub async fn run<A: ToSocketAddrs>(&mut self, subscription_addr: A) {
@@ -35,13 +35,7 @@ impl SubscribeProposedTransaction {
                 hash: pool_tx_entry.transaction.hash.pack(),
                 proposal_id: packed_tx.proposal_short_id(),
             };
-            let raw_query = format!(
-                "INSERT INTO {}.subscribed_proposed_transaction (time, size, cycles, fee, n_inputs, n_outputs, n_cell_deps, n_header_deps, hash, proposal_id) \
-                VALUES ('{}', {}, {}, {}, {}, {}, {}, {}, '{:#x}', '{:#x}')",
-                entry.network, entry.time, entry.size, entry.cycles, entry.fee, entry.n_inputs, entry.n_outputs, entry.n_cell_deps, entry.n_header_deps,
-                entry.hash, entry.proposal_id,
-            );
-            self.query_sender.send(raw_query).unwrap();
+            self.entry_sender.send(entry.into()).unwrap();
         }
     }
 }
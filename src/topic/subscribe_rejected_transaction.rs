@@ -7,12 +7,12 @@ use tokio::net::ToSocketAddrs;
 
 pub struct SubscribeRejectedTransaction {
     node: Node,
-    query_sender: crossbeam::channel::Sender<String>,
+    entry_sender: crossbeam::channel::Sender<entry::Entry>,
 }
 
 impl SubscribeRejectedTransaction {
-    pub fn new(node: Node, query_sender: crossbeam::channel::Sender<String>) -> Self {
-        Self { node, query_sender }
+    pub fn new(node: Node, entry_sender: crossbeam::channel::Sender<entry::Entry>) -> Self {
+        Self { node, entry_sender }
     }
 
     pub async fn run<A: ToSocketAddrs>(&mut self, subscription_addr: A) {
@@ -50,13 +50,7 @@ impl SubscribeRejectedTransaction {
                 hash: pool_tx_entry.transaction.hash.pack(),
                 proposal_id: packed_tx.proposal_short_id(),
             };
-            let raw_query = format!(
-                "INSERT INTO {}.subscribed_rejected_transaction (time, reason, size, cycles, fee, n_inputs, n_outputs, n_cell_deps, n_header_deps, hash, proposal_id) \
-                VALUES ('{}', '{}', {}, {}, {}, {}, {}, {}, {}, '{:#x}', '{:#x}')",
-                entry.network, entry.time, entry.reason, entry.size, entry.cycles, entry.fee, entry.n_inputs, entry.n_outputs, entry.n_cell_deps, entry.n_header_deps,
-                entry.hash, entry.proposal_id,
-            );
-            self.query_sender.send(raw_query).unwrap();
+            self.entry_sender.send(entry.into()).unwrap();
         }
     }
 }
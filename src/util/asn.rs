@@ -0,0 +1,242 @@
+//! Autonomous system of peer IPs, and the kind of network it is.
+//!
+//! `AsnDatabase` range-searches the ASN CSV files of https://github.com/sapics/ip-location-db,
+//! `asn-ipv4.csv` and `asn-ipv6.csv`, loaded in memory like `geo::CsvGeoResolver`.
+
+use crate::util::geo::{ip_to_u128, split_csv_line};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Kind of network an autonomous system serves
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HostingClass {
+    // Public clouds, e.g. AWS, Alibaba Cloud
+    Cloud,
+    // VPS and dedicated server hosters, e.g. Hetzner, OVH
+    Hosting,
+    // Anything else, mostly ISPs serving homes and offices
+    Residential,
+}
+
+impl HostingClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HostingClass::Cloud => "cloud",
+            HostingClass::Hosting => "hosting",
+            HostingClass::Residential => "residential",
+        }
+    }
+}
+
+// (ASN, provider, class) of the well-known providers, several ASNs may belong to one provider
+const PROVIDERS: &[(u32, &str, HostingClass)] = &[
+    (16509, "AWS", HostingClass::Cloud),
+    (14618, "AWS", HostingClass::Cloud),
+    (8987, "AWS", HostingClass::Cloud),
+    (45102, "Alibaba Cloud", HostingClass::Cloud),
+    (37963, "Alibaba Cloud", HostingClass::Cloud),
+    (134963, "Alibaba Cloud", HostingClass::Cloud),
+    // Google's own services, only AS396982 serves Google Cloud customers
+    (15169, "Google", HostingClass::Cloud),
+    (19527, "Google", HostingClass::Cloud),
+    (396982, "Google Cloud", HostingClass::Cloud),
+    (8075, "Microsoft Azure", HostingClass::Cloud),
+    (45090, "Tencent Cloud", HostingClass::Cloud),
+    (132203, "Tencent Cloud", HostingClass::Cloud),
+    (136907, "Huawei Cloud", HostingClass::Cloud),
+    (55990, "Huawei Cloud", HostingClass::Cloud),
+    (31898, "Oracle Cloud", HostingClass::Cloud),
+    (24940, "Hetzner", HostingClass::Hosting),
+    (213230, "Hetzner", HostingClass::Hosting),
+    (16276, "OVH", HostingClass::Hosting),
+    (14061, "DigitalOcean", HostingClass::Hosting),
+    (63949, "Linode", HostingClass::Hosting),
+    (20473, "Vultr", HostingClass::Hosting),
+    (51167, "Contabo", HostingClass::Hosting),
+    (40021, "Contabo", HostingClass::Hosting),
+    (12876, "Scaleway", HostingClass::Hosting),
+    (60781, "Leaseweb", HostingClass::Hosting),
+    (28753, "Leaseweb", HostingClass::Hosting),
+];
+
+// Organisation names of the other hosters usually contain one of these words, matched as whole
+// words so that e.g. "Colombia" does not match
+const HOSTING_KEYWORDS: &[&str] = &[
+    "hosting",
+    "host",
+    "server",
+    "servers",
+    "datacenter",
+    "data center",
+    "cloud",
+    "vps",
+    "colocation",
+];
+
+/// Autonomous system of an IP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsnInfo {
+    pub asn: u32,
+    pub organization: String,
+    // Well-known provider operating the AS, see `PROVIDERS`
+    pub provider: Option<&'static str>,
+    pub class: HostingClass,
+}
+
+impl AsnInfo {
+    pub fn new(asn: u32, organization: String) -> Self {
+        let (provider, class) = classify(asn, &organization);
+        Self {
+            asn,
+            organization,
+            provider,
+            class,
+        }
+    }
+}
+
+/// Classify an AS by its number first, then by the keywords of its organisation name
+pub fn classify(asn: u32, organization: &str) -> (Option<&'static str>, HostingClass) {
+    if let Some((_, provider, class)) = PROVIDERS.iter().find(|(number, _, _)| *number == asn) {
+        return (Some(provider), *class);
+    }
+    let words = organization
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    if HOSTING_KEYWORDS.iter().any(|keyword| {
+        let keyword = keyword.split(' ').collect::<Vec<_>>();
+        words
+            .windows(keyword.len())
+            .any(|window| window == keyword.as_slice())
+    }) {
+        (None, HostingClass::Hosting)
+    } else {
+        (None, HostingClass::Residential)
+    }
+}
+
+/// AsnDatabase keeps IP ranges sorted by start, IPv4 ones mapped into IPv6, and the distinct
+/// autonomous systems they point to. The empty one resolves nothing.
+#[derive(Default)]
+pub struct AsnDatabase {
+    // (start, end, index into `systems`), sorted by start
+    ranges: Vec<(u128, u128, usize)>,
+    systems: Vec<AsnInfo>,
+}
+
+impl AsnDatabase {
+    /// Load the ASN CSV files. Panic if a file cannot be loaded.
+    pub fn open<P: AsRef<Path>>(csv_paths: &[P]) -> Self {
+        let mut database = Self::default();
+        for path in csv_paths {
+            let path = path.as_ref();
+            let file = File::open(path)
+                .unwrap_or_else(|err| panic!("open ASN CSV {}, error: {}", path.display(), err));
+            let n = database
+                .load(BufReader::new(file))
+                .unwrap_or_else(|err| panic!("load ASN CSV {}, error: {}", path.display(), err));
+            log::info!("Loaded {} ASN ranges from {}", n, path.display());
+        }
+        database
+    }
+
+    /// Load the rows `ip_range_start,ip_range_end,autonomous_system_number,
+    /// autonomous_system_organization`, return the number of ranges loaded. Malformed rows are
+    /// skipped.
+    pub fn load<R: BufRead>(&mut self, reader: R) -> std::io::Result<usize> {
+        let mut system_indexes = self
+            .systems
+            .iter()
+            .enumerate()
+            .map(|(index, system)| (system.asn, index))
+            .collect::<HashMap<_, _>>();
+        let mut n = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let fields = split_csv_line(&line);
+            if fields.len() < 4 {
+                continue;
+            }
+            let (start, end) = match (ip_to_u128(&fields[0]), ip_to_u128(&fields[1])) {
+                (Some(start), Some(end)) if start <= end => (start, end),
+                _ => continue,
+            };
+            let asn = match u32::from_str(fields[2].trim()) {
+                Ok(asn) => asn,
+                Err(_) => continue,
+            };
+            let systems = &mut self.systems;
+            let organization = &fields[3];
+            let index = *system_indexes.entry(asn).or_insert_with(|| {
+                systems.push(AsnInfo::new(asn, organization.clone()));
+                systems.len() - 1
+            });
+            self.ranges.push((start, end, index));
+            n += 1;
+        }
+        self.ranges.sort_unstable_by_key(|(start, _, _)| *start);
+        Ok(n)
+    }
+
+    /// Return the autonomous system of `ip`, None if unknown
+    pub fn lookup(&self, ip: &str) -> Option<AsnInfo> {
+        let ip = ip_to_u128(ip)?;
+        let i = self.ranges.partition_point(|(start, _, _)| *start <= ip);
+        let (_, end, index) = self.ranges.get(i.checked_sub(1)?)?;
+        if ip <= *end {
+            self.systems.get(*index).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_asn_database() {
+    let csv = "3.0.0.0,3.0.255.255,16509,AMAZON-02\n\
+        5.9.0.0,5.9.255.255,24940,Hetzner Online GmbH\n\
+        8.8.8.0,8.8.8.255,15169,GOOGLE\n\
+        31.13.0.0,31.13.0.255,64500,\"Example Hosting, Ltd\"\n\
+        36.0.0.0,36.0.255.255,4134,Chinanet\n\
+        45.0.0.0,45.0.0.255,64501,Colombia Movil\n\
+        46.0.0.0,46.0.0.255,64502,EXAMPLE-DC Data Center\n\
+        2a01:4f8::,2a01:4f8:ffff:ffff:ffff:ffff:ffff:ffff,24940,Hetzner Online GmbH\n\
+        malformed\n";
+    let mut database = AsnDatabase::default();
+    assert_eq!(database.load(csv.as_bytes()).unwrap(), 8);
+
+    let aws = database.lookup("3.0.1.2").unwrap();
+    assert_eq!(aws.asn, 16509);
+    assert_eq!(aws.organization, "AMAZON-02");
+    assert_eq!(aws.provider, Some("AWS"));
+    assert_eq!(aws.class, HostingClass::Cloud);
+    let hetzner = database.lookup("2a01:4f8::1").unwrap();
+    assert_eq!(hetzner.provider, Some("Hetzner"));
+    assert_eq!(hetzner.class, HostingClass::Hosting);
+    // Classified by organisation name
+    let hoster = database.lookup("31.13.0.1").unwrap();
+    assert_eq!(hoster.organization, "Example Hosting, Ltd");
+    assert_eq!(hoster.provider, None);
+    assert_eq!(hoster.class, HostingClass::Hosting);
+    assert_eq!(
+        database.lookup("46.0.0.1").unwrap().class,
+        HostingClass::Hosting
+    );
+    assert_eq!(
+        database.lookup("36.0.0.1").unwrap().class,
+        HostingClass::Residential
+    );
+    // Keywords are whole words
+    assert_eq!(
+        database.lookup("45.0.0.1").unwrap().class,
+        HostingClass::Residential
+    );
+    assert_eq!(database.lookup("8.8.8.8").unwrap().provider, Some("Google"));
+    assert_eq!(database.lookup("9.9.9.9"), None);
+    assert_eq!(AsnDatabase::default().lookup("3.0.1.2"), None);
+}
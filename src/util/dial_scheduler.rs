@@ -0,0 +1,329 @@
+use crate::util::multiaddr::PeerAddress;
+use p2p::multiaddr::Multiaddr;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+// Backoff of a failing address, doubled per consecutive failure
+const INITIAL_DIAL_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_DIAL_BACKOFF: Duration = Duration::from_secs(60 * 60 * 6);
+// Reachable addresses are dialed again after this, to keep them online
+const REDIAL_INTERVAL: Duration = Duration::from_secs(60);
+// Dials neither succeeded nor failed within this are counted as failed
+const DIAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// DialScheduler decides which observed addresses to dial next.
+///
+/// Never-dialed addresses go first, in observed order. The others are dialed again once due:
+/// shortly after a success, or after an exponential backoff per consecutive failure. At most
+/// `max_in_flight` dials are pending at a time. Without redial, every address is dialed once.
+/// Addresses of unsupported transports are known but never dialed.
+pub struct DialScheduler {
+    max_in_flight: usize,
+    redial: bool,
+    // #{ address => state }
+    states: HashMap<Multiaddr, DialState>,
+    // Never-dialed addresses
+    fresh: VecDeque<Multiaddr>,
+    // #{ due time => addresses }, stale items not matching `states` are skipped
+    due: BTreeMap<Instant, VecDeque<Multiaddr>>,
+    // #{ address => dial time }
+    in_flight: HashMap<Multiaddr, Instant>,
+    round: Round,
+}
+
+struct DialState {
+    failures: u32,
+    // None while never dialed or in flight
+    next_dial_time: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Round {
+    dialed: HashSet<Multiaddr>,
+    succeeded: HashSet<Multiaddr>,
+    n_failures: usize,
+}
+
+/// Crawl coverage of a round, i.e. since the previous `DialScheduler::finish_round`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoundStats {
+    pub n_known: usize,
+    // Distinct addresses dialed within the round
+    pub n_dialed: usize,
+    // Distinct addresses dialed successfully within the round
+    pub n_succeeded: usize,
+    pub n_failures: usize,
+    pub n_never_dialed: usize,
+    // Addresses that failed last time
+    pub n_backing_off: usize,
+    // #{ transport => coverage }, see `PeerAddress::transport`
+    pub transports: BTreeMap<&'static str, TransportStats>,
+}
+
+/// Crawl coverage of the addresses of a transport within a round
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransportStats {
+    pub n_known: usize,
+    pub n_dialed: usize,
+    pub n_succeeded: usize,
+}
+
+impl DialScheduler {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight,
+            redial: true,
+            states: Default::default(),
+            fresh: Default::default(),
+            due: Default::default(),
+            in_flight: Default::default(),
+            round: Default::default(),
+        }
+    }
+
+    /// Dial every address once, e.g. for a snapshot
+    pub fn disable_redial(&mut self) {
+        self.redial = false;
+    }
+
+    /// Return whether there is nothing left to dial, nor dial pending
+    pub fn is_exhausted(&self) -> bool {
+        self.in_flight.is_empty()
+            && self.due.is_empty()
+            && !self.fresh.iter().any(|address| self.is_fresh(address))
+    }
+
+    /// Add an observed address, known ones are ignored
+    pub fn add(&mut self, address: Multiaddr) {
+        if self.states.contains_key(&address) {
+            return;
+        }
+        self.states.insert(
+            address.clone(),
+            DialState {
+                failures: 0,
+                next_dial_time: None,
+            },
+        );
+        if PeerAddress::from(&address).is_dialable() {
+            self.fresh.push_back(address);
+        }
+    }
+
+    /// Forget a pruned address
+    pub fn remove(&mut self, address: &Multiaddr) {
+        self.states.remove(address);
+        self.in_flight.remove(address);
+    }
+
+    /// Return the addresses to dial now, within the free dial budget, and mark them in flight
+    pub fn next(&mut self, now: Instant) -> Vec<Multiaddr> {
+        let timeouts = self
+            .in_flight
+            .iter()
+            .filter(|(_, dial_time)| now.saturating_duration_since(**dial_time) > DIAL_TIMEOUT)
+            .map(|(address, _)| address.clone())
+            .collect::<Vec<_>>();
+        for address in timeouts {
+            self.failed(&address, now);
+        }
+
+        let mut addresses = Vec::new();
+        while self.in_flight.len() < self.max_in_flight {
+            match self.pop_fresh().or_else(|| self.pop_due(now)) {
+                Some(address) => {
+                    self.in_flight.insert(address.clone(), now);
+                    self.round.dialed.insert(address.clone());
+                    addresses.push(address);
+                }
+                None => break,
+            }
+        }
+        addresses
+    }
+
+    /// Record a successful dial of `address`
+    pub fn succeeded(&mut self, address: &Multiaddr, now: Instant) {
+        if self.in_flight.remove(address).is_none() {
+            return;
+        }
+        if let Some(state) = self.states.get_mut(address) {
+            state.failures = 0;
+            self.round.succeeded.insert(address.clone());
+            if !self.redial {
+                return;
+            }
+            let next_dial_time = now + REDIAL_INTERVAL;
+            state.next_dial_time = Some(next_dial_time);
+            self.due.entry(next_dial_time).or_default().push_back(address.clone());
+        }
+    }
+
+    /// Return the coverage since the previous call and start a new round
+    pub fn finish_round(&mut self) -> RoundStats {
+        let round = std::mem::take(&mut self.round);
+        let mut transports = BTreeMap::<_, TransportStats>::new();
+        for address in self.states.keys() {
+            let transport = PeerAddress::from(address).transport();
+            let stats = transports.entry(transport).or_default();
+            stats.n_known += 1;
+            stats.n_dialed += round.dialed.contains(address) as usize;
+            stats.n_succeeded += round.succeeded.contains(address) as usize;
+        }
+        RoundStats {
+            n_known: self.states.len(),
+            n_dialed: round.dialed.len(),
+            n_succeeded: round.succeeded.len(),
+            n_failures: round.n_failures,
+            n_never_dialed: self
+                .fresh
+                .iter()
+                .filter(|address| self.is_fresh(address))
+                .count(),
+            n_backing_off: self
+                .states
+                .values()
+                .filter(|state| state.failures > 0)
+                .count(),
+            transports,
+        }
+    }
+
+    /// Record a failed dial of `address`
+    pub fn failed(&mut self, address: &Multiaddr, now: Instant) {
+        if self.in_flight.remove(address).is_none() {
+            return;
+        }
+        if let Some(state) = self.states.get_mut(address) {
+            state.failures += 1;
+            self.round.n_failures += 1;
+            if !self.redial {
+                return;
+            }
+            let backoff = INITIAL_DIAL_BACKOFF
+                .checked_mul(1 << (state.failures - 1).min(16))
+                .unwrap_or(MAX_DIAL_BACKOFF)
+                .min(MAX_DIAL_BACKOFF);
+            let next_dial_time = now + backoff;
+            state.next_dial_time = Some(next_dial_time);
+            self.due.entry(next_dial_time).or_default().push_back(address.clone());
+        }
+    }
+
+    fn is_fresh(&self, address: &Multiaddr) -> bool {
+        !self.in_flight.contains_key(address)
+            && matches!(
+                self.states.get(address),
+                Some(DialState { failures: 0, next_dial_time: None })
+            )
+    }
+
+    fn pop_fresh(&mut self) -> Option<Multiaddr> {
+        while let Some(address) = self.fresh.pop_front() {
+            if self.is_fresh(&address) {
+                return Some(address);
+            }
+        }
+        None
+    }
+
+    fn pop_due(&mut self, now: Instant) -> Option<Multiaddr> {
+        loop {
+            let mut first = self.due.first_entry()?;
+            let due_time = *first.key();
+            if due_time > now {
+                return None;
+            }
+            let address = first.get_mut().pop_front();
+            if first.get().is_empty() {
+                first.remove();
+            }
+            if let Some(address) = address {
+                if let Some(state) = self.states.get_mut(&address) {
+                    if state.next_dial_time == Some(due_time) {
+                        state.next_dial_time = None;
+                        return Some(address);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dial_scheduler() {
+    let address = |port: u16| -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    };
+    let secs = |secs: u64| Duration::from_secs(secs);
+
+    let mut scheduler = DialScheduler::new(2);
+    for port in 1..=3 {
+        scheduler.add(address(port));
+    }
+    scheduler.add(address(1));
+    // Known, but never dialed
+    scheduler.add("/memory/1234".parse().unwrap());
+
+    // Never-dialed first, within the budget
+    let t0 = Instant::now();
+    assert_eq!(scheduler.next(t0), vec![address(1), address(2)]);
+    assert!(scheduler.next(t0).is_empty());
+    scheduler.succeeded(&address(1), t0);
+    scheduler.failed(&address(2), t0);
+    assert_eq!(scheduler.next(t0), vec![address(3)]);
+
+    // Address 3 times out, the others are due after a minute
+    assert!(scheduler.next(t0 + secs(31)).is_empty());
+    assert_eq!(scheduler.next(t0 + secs(60)), vec![address(1), address(2)]);
+    assert_eq!(
+        scheduler.finish_round(),
+        RoundStats {
+            n_known: 4,
+            n_dialed: 3,
+            n_succeeded: 1,
+            n_failures: 2,
+            n_never_dialed: 0,
+            n_backing_off: 2,
+            transports: vec![
+                (
+                    "ipv4",
+                    TransportStats {
+                        n_known: 3,
+                        n_dialed: 3,
+                        n_succeeded: 1,
+                    }
+                ),
+                (
+                    "unsupported",
+                    TransportStats {
+                        n_known: 1,
+                        n_dialed: 0,
+                        n_succeeded: 0,
+                    }
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    );
+
+    // Backoff doubles per consecutive failure
+    scheduler.failed(&address(1), t0 + secs(60));
+    scheduler.failed(&address(2), t0 + secs(60));
+    assert_eq!(scheduler.next(t0 + secs(151)), vec![address(3), address(1)]);
+    scheduler.remove(&address(3));
+    assert!(scheduler.next(t0 + secs(179)).is_empty());
+    assert_eq!(scheduler.next(t0 + secs(180)), vec![address(2)]);
+
+    // Without redial
+    let mut scheduler = DialScheduler::new(2);
+    scheduler.disable_redial();
+    scheduler.add(address(1));
+    assert!(!scheduler.is_exhausted());
+    assert_eq!(scheduler.next(t0), vec![address(1)]);
+    assert!(!scheduler.is_exhausted());
+    scheduler.failed(&address(1), t0);
+    assert!(scheduler.is_exhausted());
+    assert!(scheduler.next(t0 + secs(3600)).is_empty());
+}
@@ -0,0 +1,283 @@
+use crate::entry::{Entry, IpInfo};
+use crate::util::asn::{AsnDatabase, AsnInfo};
+use crate::util::geo::{GeoInfo, GeoResolver};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+// Lookups are made in batches of at most `ENRICH_BATCH_SIZE` IPs every `ENRICH_INTERVAL`, which
+// bounds the lookup rate
+const ENRICH_INTERVAL: Duration = Duration::from_secs(1);
+const ENRICH_BATCH_SIZE: usize = 10;
+// Resolved IPs are looked up again after this if requested meanwhile, otherwise forgotten
+const ENRICH_TTL: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+// Unresolved IPs are looked up again after this
+const ENRICH_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// (network, ip)
+type Key = (String, String);
+
+/// Handle to request geolocating IPs, cheap to clone. The default one drops the requests.
+#[derive(Clone, Default)]
+pub struct Enrichment {
+    sender: Option<mpsc::UnboundedSender<Key>>,
+}
+
+impl Enrichment {
+    /// Request geolocating `ip` of `network`, without blocking. Known IPs are ignored by the
+    /// worker until their TTL expires.
+    pub fn enrich(&self, network: &str, ip: &str) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send((network.to_string(), ip.to_string()));
+        }
+    }
+}
+
+/// Enricher geolocates the requested IPs and looks up their autonomous systems off the crawler
+/// threads, and sends the results as `IpInfo` entries. DNS names, e.g. of `/dns4` peers, are
+/// resolved first, the entries keep the names as their `ip`.
+pub struct Enricher {
+    resolver: Arc<dyn GeoResolver>,
+    asn_database: Arc<AsnDatabase>,
+    entry_sender: crossbeam::channel::Sender<Entry>,
+    receiver: mpsc::UnboundedReceiver<Key>,
+    queue: EnrichQueue,
+}
+
+impl Enricher {
+    pub fn new(
+        resolver: Arc<dyn GeoResolver>,
+        asn_database: Arc<AsnDatabase>,
+        entry_sender: crossbeam::channel::Sender<Entry>,
+    ) -> (Self, Enrichment) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let enricher = Self {
+            resolver,
+            asn_database,
+            entry_sender,
+            receiver,
+            queue: EnrichQueue::new(ENRICH_TTL, ENRICH_RETRY_INTERVAL),
+        };
+        (
+            enricher,
+            Enrichment {
+                sender: Some(sender),
+            },
+        )
+    }
+
+    /// Run until every `Enrichment` handle is dropped
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(ENRICH_INTERVAL);
+        loop {
+            tokio::select! {
+                key = self.receiver.recv() => match key {
+                    Some(key) => self.queue.push(key, Instant::now()),
+                    None => break,
+                },
+                _ = interval.tick() => {
+                    let batch = self.queue.pop_batch(ENRICH_BATCH_SIZE, Instant::now());
+                    if batch.is_empty() {
+                        continue;
+                    }
+                    // Lookups may block, e.g. on DNS or ipinfo.io
+                    let resolver = Arc::clone(&self.resolver);
+                    let asn_database = Arc::clone(&self.asn_database);
+                    let results = tokio::task::spawn_blocking(move || {
+                        batch
+                            .into_iter()
+                            .map(|key| {
+                                let ip = resolve_host(&key.1);
+                                let geo_info = ip.as_deref().and_then(|ip| resolver.resolve(ip));
+                                let asn_info = ip.as_deref().and_then(|ip| asn_database.lookup(ip));
+                                (key, geo_info, asn_info)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .await
+                    .unwrap_or_default();
+                    for (key, geo_info, asn_info) in results {
+                        let ok = geo_info.is_some() || asn_info.is_some();
+                        self.queue.resolved(key.clone(), ok, Instant::now());
+                        if geo_info.is_none() {
+                            log::warn!("Failed to geolocate {}", key.1);
+                        }
+                        if ok {
+                            self.send(key, geo_info.unwrap_or_default(), asn_info);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Unresolved fields are left empty, the sink keeps their previous values
+    fn send(&self, (network, ip): Key, geo_info: GeoInfo, asn_info: Option<AsnInfo>) {
+        let GeoInfo {
+            country,
+            region,
+            city,
+            company,
+            latitude,
+            longitude,
+        } = geo_info;
+        let entry = IpInfo {
+            network,
+            ip,
+            country,
+            city,
+            region,
+            company,
+            latitude,
+            longitude,
+            asn: asn_info.as_ref().map(|asn_info| i64::from(asn_info.asn)),
+            as_organization: asn_info
+                .as_ref()
+                .map(|asn_info| asn_info.organization.clone()),
+            provider: asn_info
+                .as_ref()
+                .and_then(|asn_info| asn_info.provider.map(String::from)),
+            hosting_class: asn_info.map(|asn_info| asn_info.class.as_str().to_string()),
+        };
+        self.entry_sender.send(entry.into()).unwrap();
+    }
+}
+
+/// Geolocate `hosts` in batches at the rate of the enrichment worker, for the jobs that run
+/// without it, e.g. snapshots. Return the locations found.
+pub async fn geolocate(resolver: Arc<dyn GeoResolver>, mut hosts: Vec<String>) -> HashMap<String, GeoInfo> {
+    hosts.sort_unstable();
+    hosts.dedup();
+    let mut located = HashMap::with_capacity(hosts.len());
+    let mut interval = tokio::time::interval(ENRICH_INTERVAL);
+    for batch in hosts.chunks(ENRICH_BATCH_SIZE) {
+        interval.tick().await;
+        // Lookups may block, e.g. on DNS or ipinfo.io
+        let resolver = Arc::clone(&resolver);
+        let batch = batch.to_vec();
+        let results = tokio::task::spawn_blocking(move || {
+            batch
+                .into_iter()
+                .filter_map(|host| {
+                    let geo_info = resolve_host(&host).and_then(|ip| resolver.resolve(&ip))?;
+                    Some((host, geo_info))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        located.extend(results);
+    }
+    located
+}
+
+// Return the IP of `host`, resolving DNS names with the system resolver. Blocks while resolving.
+fn resolve_host(host: &str) -> Option<String> {
+    if host.parse::<IpAddr>().is_ok() {
+        return Some(host.to_string());
+    }
+    (host, 0)
+        .to_socket_addrs()
+        .ok()?
+        .next()
+        .map(|socket_addr| socket_addr.ip().to_string())
+}
+
+/// EnrichQueue deduplicates the requested IPs, and schedules their lookups: new ones in
+/// request order, then the known ones once their TTL expires. Known IPs not requested again
+/// within the TTL are forgotten instead of being looked up again.
+struct EnrichQueue {
+    ttl: Duration,
+    retry_interval: Duration,
+    pending: VecDeque<Key>,
+    // Keys in `pending` or being looked up
+    queued: HashSet<Key>,
+    // #{ key => (next lookup time, last request time) } of the looked up keys
+    known: HashMap<Key, (Instant, Instant)>,
+    // (next lookup time, key) of the looked up keys not queued
+    due: BTreeSet<(Instant, Key)>,
+}
+
+impl EnrichQueue {
+    fn new(ttl: Duration, retry_interval: Duration) -> Self {
+        Self {
+            ttl,
+            retry_interval,
+            pending: Default::default(),
+            queued: Default::default(),
+            known: Default::default(),
+            due: Default::default(),
+        }
+    }
+
+    fn push(&mut self, key: Key, now: Instant) {
+        if let Some((_, requested)) = self.known.get_mut(&key) {
+            *requested = now;
+        } else if self.queued.insert(key.clone()) {
+            self.pending.push_back(key);
+        }
+    }
+
+    // Return at most `n` keys to look up, requeueing the expired ones behind the new ones
+    fn pop_batch(&mut self, n: usize, now: Instant) -> Vec<Key> {
+        while let Some((due_time, key)) = self.due.iter().next().cloned() {
+            if due_time > now {
+                break;
+            }
+            self.due.remove(&(due_time, key.clone()));
+            match self.known.get(&key) {
+                Some((_, requested)) if now.duration_since(*requested) < self.ttl => {
+                    self.queued.insert(key.clone());
+                    self.pending.push_back(key);
+                }
+                _ => {
+                    self.known.remove(&key);
+                }
+            }
+        }
+        let n = n.min(self.pending.len());
+        self.pending.drain(..n).collect()
+    }
+
+    fn resolved(&mut self, key: Key, ok: bool, now: Instant) {
+        self.queued.remove(&key);
+        let due_time = now + if ok { self.ttl } else { self.retry_interval };
+        let requested = self.known.get(&key).map_or(now, |(_, requested)| *requested);
+        self.known.insert(key.clone(), (due_time, requested));
+        self.due.insert((due_time, key));
+    }
+}
+
+#[test]
+fn test_enrich_queue() {
+    let key = |ip: &str| ("ckb".to_string(), ip.to_string());
+    let secs = Duration::from_secs;
+    let mut queue = EnrichQueue::new(secs(100), secs(10));
+
+    let t0 = Instant::now();
+    queue.push(key("1.1.1.1"), t0);
+    queue.push(key("2.2.2.2"), t0);
+    queue.push(key("1.1.1.1"), t0);
+    queue.push(key("3.3.3.3"), t0);
+    assert_eq!(queue.pop_batch(2, t0), vec![key("1.1.1.1"), key("2.2.2.2")]);
+    // Being looked up
+    queue.push(key("1.1.1.1"), t0);
+    queue.resolved(key("1.1.1.1"), true, t0);
+    queue.resolved(key("2.2.2.2"), false, t0);
+    assert_eq!(queue.pop_batch(2, t0), vec![key("3.3.3.3")]);
+    queue.resolved(key("3.3.3.3"), true, t0);
+
+    // Known until their TTL or retry interval expires
+    queue.push(key("1.1.1.1"), t0 + secs(5));
+    assert!(queue.pop_batch(10, t0 + secs(5)).is_empty());
+    assert_eq!(queue.pop_batch(10, t0 + secs(10)), vec![key("2.2.2.2")]);
+    queue.resolved(key("2.2.2.2"), true, t0 + secs(10));
+
+    // 3.3.3.3 was not requested again within the TTL, it is forgotten
+    assert_eq!(queue.pop_batch(10, t0 + secs(100)), vec![key("1.1.1.1")]);
+    assert!(!queue.known.contains_key(&key("3.3.3.3")));
+    queue.push(key("3.3.3.3"), t0 + secs(100));
+    assert_eq!(queue.pop_batch(10, t0 + secs(100)), vec![key("3.3.3.3")]);
+}
@@ -0,0 +1,321 @@
+//! Geolocation of peer IPs.
+//!
+//! `CsvGeoResolver` range-searches the GeoLite2 city ranges in memory, loaded from the CSV files
+//! of https://github.com/sapics/ip-location-db, the same ones `sql/import_ip_data.sh` imports
+//! into `common_info.ip_info`. `IpInfoResolver` calls the ipinfo.io API, and is only used as a
+//! fallback when enabled.
+
+use crate::entry::{GeoCity, SnapshotPeer};
+use crate::util::ipinfo::lookup_ipinfo;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Location of an IP
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GeoInfo {
+    pub country: String,
+    pub region: String,
+    pub city: String,
+    // Only known by ipinfo.io
+    pub company: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+pub trait GeoResolver: Send + Sync {
+    /// Return the location of `ip`, None if unknown
+    fn resolve(&self, ip: &str) -> Option<GeoInfo>;
+}
+
+/// Resolvers tried in turn, the empty one resolves nothing
+#[derive(Default)]
+pub struct GeoResolvers(Vec<Box<dyn GeoResolver>>);
+
+impl GeoResolver for GeoResolvers {
+    fn resolve(&self, ip: &str) -> Option<GeoInfo> {
+        self.0.iter().find_map(|resolver| resolver.resolve(ip))
+    }
+}
+
+/// Build the resolver of the configured CSV files, falling back to ipinfo.io if
+/// `ipinfo_fallback`. Panic if a CSV file cannot be loaded.
+pub fn geo_resolver<P: AsRef<Path>>(csv_paths: &[P], ipinfo_fallback: bool) -> Arc<dyn GeoResolver> {
+    let mut resolvers: Vec<Box<dyn GeoResolver>> = Vec::new();
+    if !csv_paths.is_empty() {
+        let mut resolver = CsvGeoResolver::default();
+        for path in csv_paths {
+            let path = path.as_ref();
+            let file = File::open(path)
+                .unwrap_or_else(|err| panic!("open GeoIP CSV {}, error: {}", path.display(), err));
+            let n = resolver
+                .load(BufReader::new(file))
+                .unwrap_or_else(|err| panic!("load GeoIP CSV {}, error: {}", path.display(), err));
+            log::info!("Loaded {} GeoIP ranges from {}", n, path.display());
+        }
+        resolvers.push(Box::new(resolver));
+    }
+    if ipinfo_fallback {
+        resolvers.push(Box::new(IpInfoResolver));
+    }
+    Arc::new(GeoResolvers(resolvers))
+}
+
+/// Locations looked up beforehand, e.g. by `enrichment::geolocate`
+impl GeoResolver for HashMap<String, GeoInfo> {
+    fn resolve(&self, ip: &str) -> Option<GeoInfo> {
+        self.get(ip).cloned()
+    }
+}
+
+/// CsvGeoResolver keeps IP ranges sorted by start, IPv4 ones mapped into IPv6, and the distinct
+/// locations they point to.
+#[derive(Default)]
+pub struct CsvGeoResolver {
+    // (start, end, index into `locations`), sorted by start
+    ranges: Vec<(u128, u128, usize)>,
+    locations: Vec<GeoInfo>,
+}
+
+impl CsvGeoResolver {
+    /// Load the rows `ip_range_start,ip_range_end,country_code,state1,state2,city,postcode,
+    /// latitude,longitude,timezone`, return the number of ranges loaded. Malformed rows are
+    /// skipped.
+    pub fn load<R: BufRead>(&mut self, reader: R) -> std::io::Result<usize> {
+        let mut location_indexes = self
+            .locations
+            .iter()
+            .enumerate()
+            .map(|(index, location)| (location_key(location), index))
+            .collect::<HashMap<_, _>>();
+        let mut n = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let fields = split_csv_line(&line);
+            if fields.len() < 9 {
+                continue;
+            }
+            let (start, end) = match (ip_to_u128(&fields[0]), ip_to_u128(&fields[1])) {
+                (Some(start), Some(end)) if start <= end => (start, end),
+                _ => continue,
+            };
+            let location = GeoInfo {
+                country: fields[2].clone(),
+                region: fields[3].clone(),
+                city: fields[5].clone(),
+                company: String::new(),
+                latitude: f64::from_str(&fields[7]).ok(),
+                longitude: f64::from_str(&fields[8]).ok(),
+            };
+            let locations = &mut self.locations;
+            let index = *location_indexes
+                .entry(location_key(&location))
+                .or_insert_with(|| {
+                    locations.push(location);
+                    locations.len() - 1
+                });
+            self.ranges.push((start, end, index));
+            n += 1;
+        }
+        self.ranges.sort_unstable_by_key(|(start, _, _)| *start);
+        Ok(n)
+    }
+}
+
+impl GeoResolver for CsvGeoResolver {
+    fn resolve(&self, ip: &str) -> Option<GeoInfo> {
+        let ip = ip_to_u128(ip)?;
+        let i = self.ranges.partition_point(|(start, _, _)| *start <= ip);
+        let (_, end, index) = self.ranges.get(i.checked_sub(1)?)?;
+        if ip <= *end {
+            self.locations.get(*index).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+/// IpInfoResolver looks up ipinfo.io, see `lookup_ipinfo`. It blocks until the API answers.
+pub struct IpInfoResolver;
+
+impl GeoResolver for IpInfoResolver {
+    fn resolve(&self, ip: &str) -> Option<GeoInfo> {
+        let details = lookup_ipinfo(ip).ok()?;
+        let mut loc = details.loc.split(',');
+        Some(GeoInfo {
+            country: details.country,
+            region: details.region,
+            city: details.city,
+            company: details.company.map(|company| company.name).unwrap_or_default(),
+            latitude: loc.next().and_then(|s| f64::from_str(s).ok()),
+            longitude: loc.next().and_then(|s| f64::from_str(s).ok()),
+        })
+    }
+}
+
+/// Count the peers of a snapshot per city and version. A city takes the coordinates of its first
+/// geolocated peer.
+pub fn geo_cities(peers: &[SnapshotPeer], resolver: &dyn GeoResolver) -> Vec<GeoCity> {
+    // #{ (country, region, city, version) => (latitude, longitude, n_nodes) }
+    let mut cities: BTreeMap<(String, String, String, String), (Option<f64>, Option<f64>, i32)> =
+        BTreeMap::new();
+    let mut coordinates: HashMap<(String, String, String), (Option<f64>, Option<f64>)> =
+        HashMap::new();
+    for peer in peers {
+        let geo_info = resolver.resolve(&peer.ip).unwrap_or_default();
+        let (latitude, longitude) = *coordinates
+            .entry((
+                geo_info.country.clone(),
+                geo_info.region.clone(),
+                geo_info.city.clone(),
+            ))
+            .or_insert((geo_info.latitude, geo_info.longitude));
+        let city = cities
+            .entry((
+                geo_info.country,
+                geo_info.region,
+                geo_info.city,
+                peer.version.clone(),
+            ))
+            .or_insert((latitude, longitude, 0));
+        city.2 += 1;
+    }
+    let (network, snapshot_id) = match peers.first() {
+        Some(peer) => (peer.network.clone(), peer.snapshot_id),
+        None => return Vec::new(),
+    };
+    cities
+        .into_iter()
+        .map(
+            |((country, region, city, version), (latitude, longitude, n_nodes))| GeoCity {
+                network: network.clone(),
+                snapshot_id,
+                country,
+                region,
+                city,
+                latitude,
+                longitude,
+                version,
+                n_nodes,
+            },
+        )
+        .collect()
+}
+
+fn location_key(location: &GeoInfo) -> (String, String, String, String) {
+    (
+        location.country.clone(),
+        location.region.clone(),
+        location.city.clone(),
+        format!("{:?},{:?}", location.latitude, location.longitude),
+    )
+}
+
+// IPv4 addresses are mapped into IPv6, so both kinds share one range space
+pub fn ip_to_u128(ip: &str) -> Option<u128> {
+    match IpAddr::from_str(ip.trim()).ok()? {
+        IpAddr::V4(ip4) => Some(u128::from(ip4.to_ipv6_mapped())),
+        IpAddr::V6(ip6) => Some(u128::from(ip6)),
+    }
+}
+
+// Split a CSV line, fields may be double-quoted with `""` as an escaped quote
+pub fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[test]
+fn test_csv_geo_resolver() {
+    let csv = "1.0.0.0,1.0.0.255,AU,Queensland,,Brisbane,4000,-27.4679,153.0281,Australia/Brisbane\n\
+        1.0.4.0,1.0.7.255,AU,Victoria,,Melbourne,3000,-37.8159,144.9669,Australia/Melbourne\n\
+        8.8.8.0,8.8.8.255,US,\"Washington, D.C.\",,Washington,,,,\n\
+        2001:200::,2001:200:ffff:ffff:ffff:ffff:ffff:ffff,JP,Tokyo,,Tokyo,100-0001,35.6895,139.6917,Asia/Tokyo\n\
+        3.0.0.0,3.0.0.255,US,Oregon,,Portland,97201,45.5152,-122.6784,America/Los_Angeles\n\
+        3.0.1.0,3.0.1.255,US,Maine,,Portland,04101,43.6591,-70.2568,America/New_York\n\
+        malformed\n";
+    let mut resolver = CsvGeoResolver::default();
+    assert_eq!(resolver.load(csv.as_bytes()).unwrap(), 6);
+
+    let brisbane = resolver.resolve("1.0.0.1").unwrap();
+    assert_eq!(brisbane.city, "Brisbane");
+    assert_eq!(brisbane.latitude, Some(-27.4679));
+    assert_eq!(resolver.resolve("1.0.7.255").unwrap().city, "Melbourne");
+    assert_eq!(resolver.resolve("8.8.8.8").unwrap().region, "Washington, D.C.");
+    assert_eq!(resolver.resolve("8.8.8.8").unwrap().latitude, None);
+    assert_eq!(resolver.resolve("2001:200::1").unwrap().country, "JP");
+    // Between and beyond the ranges
+    assert_eq!(resolver.resolve("1.0.1.0"), None);
+    assert_eq!(resolver.resolve("9.9.9.9"), None);
+    assert_eq!(resolver.resolve("0.0.0.1"), None);
+    assert_eq!(resolver.resolve("seed.example.org"), None);
+
+    let peer = |ip: &str, version: &str| SnapshotPeer {
+        network: "ckb".to_string(),
+        snapshot_id: 1,
+        ip: ip.to_string(),
+        address: format!("/ip4/{}/tcp/8115", ip),
+        peer_id: String::new(),
+        version: version.to_string(),
+        node_type: 1,
+        n_reachable: 0,
+    };
+    let peers = vec![
+        peer("1.0.0.1", "0.100.0"),
+        peer("1.0.0.2", "0.100.0"),
+        peer("1.0.0.3", "0.101.0"),
+        peer("1.0.4.1", "0.100.0"),
+        peer("9.9.9.9", "0.100.0"),
+        peer("3.0.0.1", "0.100.0"),
+        peer("3.0.1.1", "0.100.0"),
+    ];
+    let cities = geo_cities(&peers, &resolver)
+        .into_iter()
+        .map(|city| (city.country, city.region, city.city, city.version, city.n_nodes))
+        .collect::<Vec<_>>();
+    let city = |country: &str, region: &str, city: &str, version: &str, n_nodes| {
+        (
+            country.to_string(),
+            region.to_string(),
+            city.to_string(),
+            version.to_string(),
+            n_nodes,
+        )
+    };
+    // Cities of the same name in different regions stay apart
+    assert_eq!(
+        cities,
+        vec![
+            city("", "", "", "0.100.0", 1),
+            city("AU", "Queensland", "Brisbane", "0.100.0", 2),
+            city("AU", "Queensland", "Brisbane", "0.101.0", 1),
+            city("AU", "Victoria", "Melbourne", "0.100.0", 1),
+            city("US", "Maine", "Portland", "0.100.0", 1),
+            city("US", "Oregon", "Portland", "0.100.0", 1),
+        ]
+    );
+    assert_eq!(geo_cities(&peers[..1], &resolver)[0].latitude, Some(-27.4679));
+
+    let resolvers = GeoResolvers(vec![Box::new(resolver)]);
+    assert_eq!(resolvers.resolve("1.0.0.1").unwrap().city, "Brisbane");
+    assert_eq!(GeoResolvers::default().resolve("1.0.0.1"), None);
+}
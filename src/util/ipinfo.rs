@@ -1,10 +1,17 @@
 use ipinfo::{IpDetails, IpError, IpInfo};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use lru::LruCache;
+use std::num::NonZeroUsize;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use futures::task::SpawnExt;
 use tokio::runtime::Handle;
 
+// Looked up IPs are served from `IPINFO_CACHE` until they expire, so that the enrichment worker
+// refreshing an IP gets fresh details
+const IPINFO_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+const IPINFO_CACHE_SIZE: usize = 10000;
+
 lazy_static! {
     static ref IPINFO: Mutex<IpInfo> = {
         let ipinfo_io_token = match ::std::env::var("IPINFO_IO_TOKEN") {
@@ -16,20 +23,29 @@ lazy_static! {
         };
         let ipinfo = ipinfo::IpInfo::new(ipinfo::IpInfoConfig {
             token: ipinfo_io_token,
-            cache_size: 10000,
+            // The crate's cache never expires, leave caching to `IPINFO_CACHE`
+            cache_size: 1,
             timeout: ::std::time::Duration::from_secs(365 * 24 * 60 * 60),
             ..Default::default()
         })
         .expect("Connect to https://ipinfo.io");
         Mutex::new(ipinfo)
     };
-    static ref IPINFO_CACHE: Mutex<HashMap<String, IpDetails>> = Mutex::new(Default::default());
+    // #{ ip => (lookup time, details) }
+    static ref IPINFO_CACHE: Mutex<LruCache<String, (Instant, IpDetails)>> =
+        Mutex::new(LruCache::new(NonZeroUsize::new(IPINFO_CACHE_SIZE).unwrap()));
 }
 
 pub fn lookup_ipinfo(ip: &str) -> Result<IpDetails, IpError> {
-    if let Ok(cache) = IPINFO_CACHE.lock() {
-        if let Some(ipdetails) = cache.get(&ip.to_string()) {
-            return Ok(ipdetails.clone());
+    if let Ok(mut cache) = IPINFO_CACHE.lock() {
+        match cache.get(ip) {
+            Some((looked_up_at, ipdetails)) if looked_up_at.elapsed() < IPINFO_CACHE_TTL => {
+                return Ok(ipdetails.clone());
+            }
+            Some(_) => {
+                cache.pop(ip);
+            }
+            None => {}
         }
     }
 
@@ -38,7 +54,7 @@ pub fn lookup_ipinfo(ip: &str) -> Result<IpDetails, IpError> {
         match lookup_info {
             Ok(ipdetails) => {
                 if let Ok(mut cache) = IPINFO_CACHE.lock() {
-                    cache.insert(ip.to_string(), ipdetails.to_owned());
+                    cache.put(ip.to_string(), (Instant::now(), ipdetails.to_owned()));
                 }
 
                 return Ok(ipdetails.to_owned());
@@ -60,14 +76,14 @@ pub mod test {
         use crate::util::ipinfo::{lookup_ipinfo, IPINFO_CACHE};
         {
             let cache = IPINFO_CACHE.lock().unwrap();
-            assert!(cache.get("8.8.8.8").is_none());
+            assert!(cache.peek("8.8.8.8").is_none());
         }
 
         let _ipdetails = lookup_ipinfo("8.8.8.8").unwrap();
 
         {
             let cache = IPINFO_CACHE.lock().unwrap();
-            assert!(cache.get("8.8.8.8").is_some());
+            assert!(cache.peek("8.8.8.8").is_some());
         }
     }
 }
@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+// Latency metrics of a peer
+pub const METRIC_CONNECT: &str = "connect";
+pub const METRIC_HANDSHAKE: &str = "handshake";
+pub const METRIC_IDENTIFY: &str = "identify";
+pub const METRIC_PING: &str = "ping";
+
+// Samples kept per peer and metric within a window, the later ones are ignored
+const MAX_SAMPLES: usize = 1000;
+
+/// LatencyRecorder collects the latency samples of peers, and summarizes them into percentiles
+/// per window.
+#[derive(Default)]
+pub struct LatencyRecorder {
+    // #{ peer id => samples }
+    peers: HashMap<String, PeerSamples>,
+}
+
+#[derive(Default)]
+struct PeerSamples {
+    // The latest ip of the peer
+    ip: String,
+    // #{ metric => samples }
+    metrics: BTreeMap<&'static str, Vec<Duration>>,
+}
+
+/// Percentiles of the samples of a peer and metric within a window
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub peer_id: String,
+    pub ip: String,
+    pub metric: &'static str,
+    pub n_samples: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl LatencyRecorder {
+    pub fn record(&mut self, peer_id: &str, ip: &str, metric: &'static str, latency: Duration) {
+        let peer = self.peers.entry(peer_id.to_string()).or_default();
+        peer.ip = ip.to_string();
+        let samples = peer.metrics.entry(metric).or_default();
+        if samples.len() < MAX_SAMPLES {
+            samples.push(latency);
+        }
+    }
+
+    /// Return the summaries of the window and start a new one
+    pub fn drain(&mut self) -> Vec<LatencySummary> {
+        let mut summaries = Vec::new();
+        for (peer_id, peer) in std::mem::take(&mut self.peers) {
+            for (metric, mut samples) in peer.metrics {
+                samples.sort();
+                summaries.push(LatencySummary {
+                    peer_id: peer_id.clone(),
+                    ip: peer.ip.clone(),
+                    metric,
+                    n_samples: samples.len(),
+                    p50: percentile(&samples, 50),
+                    p90: percentile(&samples, 90),
+                    p99: percentile(&samples, 99),
+                });
+            }
+        }
+        summaries
+    }
+}
+
+/// Return the nearest-rank `p`th percentile of the non-empty ascending `sorted`
+pub fn percentile<T: Copy>(sorted: &[T], p: usize) -> T {
+    let rank = (sorted.len() * p + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+#[test]
+fn test_latency_recorder() {
+    let millis = Duration::from_millis;
+    let sorted = (1..=100).map(millis).collect::<Vec<_>>();
+    assert_eq!(percentile(&sorted, 50), millis(50));
+    assert_eq!(percentile(&sorted, 99), millis(99));
+    assert_eq!(percentile(&sorted[..1], 50), millis(1));
+    assert_eq!(percentile(&sorted[..3], 90), millis(3));
+
+    let mut recorder = LatencyRecorder::default();
+    for latency in [30, 10, 20] {
+        recorder.record("peer", "1.2.3.4", METRIC_PING, millis(latency));
+    }
+    recorder.record("peer", "5.6.7.8", METRIC_CONNECT, millis(5));
+    let mut summaries = recorder.drain();
+    summaries.sort_by_key(|summary| summary.metric);
+    assert_eq!(
+        summaries,
+        vec![
+            LatencySummary {
+                peer_id: "peer".to_string(),
+                ip: "5.6.7.8".to_string(),
+                metric: METRIC_CONNECT,
+                n_samples: 1,
+                p50: millis(5),
+                p90: millis(5),
+                p99: millis(5),
+            },
+            LatencySummary {
+                peer_id: "peer".to_string(),
+                ip: "5.6.7.8".to_string(),
+                metric: METRIC_PING,
+                n_samples: 3,
+                p50: millis(20),
+                p90: millis(30),
+                p99: millis(30),
+            },
+        ]
+    );
+    assert!(recorder.drain().is_empty());
+}
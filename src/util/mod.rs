@@ -1,5 +1,13 @@
+pub mod asn;
 pub mod bootnodes;
 pub mod crossbeam_channel_to_tokio_channel;
+pub mod dial_scheduler;
+pub mod enrichment;
+pub mod geo;
 pub mod ipinfo;
+pub mod latency;
 pub mod multiaddr;
+pub mod probe;
+pub mod propagation;
+pub mod reorg;
 pub mod global;
\ No newline at end of file
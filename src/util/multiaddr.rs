@@ -1,19 +1,123 @@
-use p2p::{multiaddr, utils::multiaddr_to_socketaddr};
+use p2p::{
+    multiaddr::{self, Multiaddr, Protocol},
+    utils::extract_peer_id,
+};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
+/// Network address of a peer, i.e. the host part of its multiaddr
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PeerAddress {
+    Ip4(Ipv4Addr),
+    // IPv4-mapped IPv6 addresses are folded into `Ip4`, other IPv6 addresses are kept as is
+    Ip6(Ipv6Addr),
+    // DNS names are not resolved here, the enrichment worker resolves them off the p2p threads
+    Dns4(String),
+    Dns6(String),
+    // Transports the crawler cannot dial, e.g. in-memory, with the full multiaddr
+    Unsupported(String),
+}
+
+impl PeerAddress {
+    /// Transport name, for reporting
+    pub fn transport(&self) -> &'static str {
+        match self {
+            PeerAddress::Ip4(_) => "ipv4",
+            PeerAddress::Ip6(_) => "ipv6",
+            PeerAddress::Dns4(_) => "dns4",
+            PeerAddress::Dns6(_) => "dns6",
+            PeerAddress::Unsupported(_) => "unsupported",
+        }
+    }
+
+    pub fn is_dialable(&self) -> bool {
+        !matches!(self, PeerAddress::Unsupported(_))
+    }
+
+    /// Return whether the address may be reachable from the Internet, i.e. neither private,
+    /// loopback, link-local nor unspecified. DNS names are assumed public.
+    pub fn is_public(&self) -> bool {
+        match self {
+            PeerAddress::Ip4(ip4) => {
+                !(ip4.is_private() || ip4.is_loopback() || ip4.is_link_local() || ip4.is_unspecified())
+            }
+            // Unique local fc00::/7 and link-local fe80::/10
+            PeerAddress::Ip6(ip6) => {
+                !(ip6.is_loopback()
+                    || ip6.is_unspecified()
+                    || (ip6.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip6.segments()[0] & 0xffc0) == 0xfe80)
+            }
+            PeerAddress::Dns4(_) | PeerAddress::Dns6(_) => true,
+            PeerAddress::Unsupported(_) => false,
+        }
+    }
+}
+
+impl From<&Multiaddr> for PeerAddress {
+    fn from(addr: &Multiaddr) -> Self {
+        match addr.iter().next() {
+            Some(Protocol::Ip4(ip4)) => PeerAddress::Ip4(ip4),
+            Some(Protocol::Ip6(ip6)) => match ip6.to_ipv4_mapped() {
+                Some(ip4) => PeerAddress::Ip4(ip4),
+                None => PeerAddress::Ip6(ip6),
+            },
+            Some(Protocol::Dns4(name)) => PeerAddress::Dns4(name.to_string()),
+            Some(Protocol::Dns6(name)) => PeerAddress::Dns6(name.to_string()),
+            _ => PeerAddress::Unsupported(addr.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerAddress::Ip4(ip4) => write!(f, "{}", ip4),
+            PeerAddress::Ip6(ip6) => write!(f, "{}", ip6),
+            PeerAddress::Dns4(name) | PeerAddress::Dns6(name) => write!(f, "{}", name),
+            PeerAddress::Unsupported(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+/// Return the host of `addr`: its IP address, or its unresolved DNS name, or the multiaddr itself
+/// for unsupported transports
 pub fn addr_to_ip(addr: &multiaddr::Multiaddr) -> String {
-    addr.iter()
-        .find_map(|protocol| match protocol {
-            multiaddr::Protocol::Ip4(ip4) => Some(ip4.to_string()),
-            multiaddr::Protocol::Ip6(ip6) => ip6
-                .to_ipv4()
-                .map(|ip4| ip4.to_string())
-                .or_else(|| Some(ip6.to_string())),
-            multiaddr::Protocol::Dns4(dns4) => Some(dns4.to_string()),
-            multiaddr::Protocol::Dns6(dns6) => Some(dns6.to_string()),
-            _ => None,
-        })
-        .unwrap_or_else(|| {
-            let socket_addr = multiaddr_to_socketaddr(&addr).unwrap();
-            socket_addr.ip().to_string()
-        })
+    PeerAddress::from(addr).to_string()
+}
+
+/// Return the base58 PeerId carried by the `/p2p/` component of `addr`
+pub fn addr_to_peer_id(addr: &multiaddr::Multiaddr) -> Option<String> {
+    extract_peer_id(addr).map(|peer_id| peer_id.to_base58())
+}
+
+#[test]
+fn test_peer_address() {
+    let peer_address = |addr: &str| PeerAddress::from(&addr.parse::<Multiaddr>().unwrap());
+
+    assert_eq!(
+        peer_address("/ip4/1.2.3.4/tcp/8115"),
+        PeerAddress::Ip4(Ipv4Addr::new(1, 2, 3, 4))
+    );
+    assert_eq!(
+        peer_address("/ip6/::ffff:1.2.3.4/tcp/8115"),
+        PeerAddress::Ip4(Ipv4Addr::new(1, 2, 3, 4))
+    );
+    // IPv4-compatible addresses like `::1` are not folded
+    assert_eq!(peer_address("/ip6/::1/tcp/8115"), PeerAddress::Ip6(Ipv6Addr::LOCALHOST));
+    assert_eq!(
+        peer_address("/dns4/seed.example.org/tcp/8115"),
+        PeerAddress::Dns4("seed.example.org".to_string())
+    );
+    assert_eq!(peer_address("/dns6/seed.example.org/tcp/8115").transport(), "dns6");
+
+    assert!(peer_address("/ip4/1.2.3.4/tcp/8115").is_public());
+    assert!(!peer_address("/ip4/192.168.1.2/tcp/8115").is_public());
+    assert!(!peer_address("/ip4/0.0.0.0/tcp/8115").is_public());
+    assert!(!peer_address("/ip6/fe80::1/tcp/8115").is_public());
+
+    let memory = peer_address("/memory/1234");
+    assert_eq!(memory.transport(), "unsupported");
+    assert!(!memory.is_dialable());
+    assert_eq!(addr_to_ip(&"/memory/1234".parse().unwrap()), "/memory/1234");
 }
@@ -0,0 +1,123 @@
+use crate::util::multiaddr::{addr_to_peer_id, PeerAddress};
+use p2p::{
+    multiaddr::{Multiaddr, Protocol},
+    secio::{handshake::Config as SecioConfig, SecioKeyPair},
+};
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// Classification of a probed address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    // Both the TCP connection and the secio handshake succeeded
+    Reachable,
+    // The TCP connection was refused
+    Refused,
+    // The TCP connection or the secio handshake did not finish in time
+    TimedOut,
+    // Reachable, but its PeerId sent an Identify naming another network over an earlier
+    // session, see `probe`
+    WrongNetwork,
+    // Any other error, e.g. unresolvable host, failed handshake, or a PeerId other than the one
+    // of the address
+    Failed,
+}
+
+impl ProbeOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeOutcome::Reachable => "reachable",
+            ProbeOutcome::Refused => "refused",
+            ProbeOutcome::TimedOut => "timed_out",
+            ProbeOutcome::WrongNetwork => "wrong_network",
+            ProbeOutcome::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Probe {
+    pub outcome: ProbeOutcome,
+    // Time to establish the TCP connection, of reachable addresses
+    pub latency: Option<Duration>,
+    // Time of the secio handshake, of reachable addresses
+    pub handshake: Option<Duration>,
+    // Base58 PeerId authenticated by the secio handshake
+    pub peer_id: Option<String>,
+}
+
+impl Probe {
+    fn failed(outcome: ProbeOutcome) -> Self {
+        Self {
+            outcome,
+            latency: None,
+            handshake: None,
+            peer_id: None,
+        }
+    }
+}
+
+/// Connect to `address` and perform a secio handshake, each within `timeout`.
+///
+/// The genesis is not checked here: the secio stream is not multiplexed, so no Identify is
+/// exchanged. The caller turns `Reachable` into `WrongNetwork` according to the Identify name
+/// the returned PeerId sent before, addresses never identified stay `Reachable`.
+pub async fn probe(address: &Multiaddr, timeout: Duration) -> Probe {
+    let port = address.iter().find_map(|protocol| match protocol {
+        Protocol::Tcp(port) => Some(port),
+        _ => None,
+    });
+    let peer_address = PeerAddress::from(address);
+    let (host, port) = match port {
+        Some(port) if peer_address.is_dialable() => (peer_address.to_string(), port),
+        _ => return Probe::failed(ProbeOutcome::Failed),
+    };
+
+    let start = Instant::now();
+    let stream = match tokio::time::timeout(timeout, TcpStream::connect((host.as_str(), port))).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) if err.kind() == ErrorKind::ConnectionRefused => {
+            return Probe::failed(ProbeOutcome::Refused)
+        }
+        Ok(Err(_)) => return Probe::failed(ProbeOutcome::Failed),
+        Err(_) => return Probe::failed(ProbeOutcome::TimedOut),
+    };
+    let latency = start.elapsed();
+
+    let start = Instant::now();
+    let handshake = SecioConfig::new(SecioKeyPair::secp256k1_generated()).handshake(stream);
+    let remote_pubkey = match tokio::time::timeout(timeout, handshake).await {
+        Ok(Ok((_, remote_pubkey, _))) => remote_pubkey,
+        Ok(Err(_)) => return Probe::failed(ProbeOutcome::Failed),
+        Err(_) => return Probe::failed(ProbeOutcome::TimedOut),
+    };
+    let handshake = start.elapsed();
+    let peer_id = remote_pubkey.peer_id().to_base58();
+    if addr_to_peer_id(address).map_or(false, |expected| expected != peer_id) {
+        return Probe::failed(ProbeOutcome::Failed);
+    }
+    Probe {
+        outcome: ProbeOutcome::Reachable,
+        latency: Some(latency),
+        handshake: Some(handshake),
+        peer_id: Some(peer_id),
+    }
+}
+
+#[tokio::test]
+async fn test_probe_refused_and_unsupported() {
+    // Bind then drop a listener, so that nothing listens on the port
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+    let probed = probe(&address, Duration::from_secs(5)).await;
+    assert_eq!(probed.outcome, ProbeOutcome::Refused);
+    assert_eq!(probed.latency, None);
+
+    let probed = probe(&"/memory/1234".parse().unwrap(), Duration::from_secs(5)).await;
+    assert_eq!(probed.outcome, ProbeOutcome::Failed);
+}
@@ -0,0 +1,194 @@
+use crate::util::latency::percentile;
+use ckb_testkit::ckb_types::packed::Byte32;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// PropagationTracker collects the first arrival of each block from each peer, and summarizes
+/// the delays of a block into percentiles once its window passes.
+pub struct PropagationTracker {
+    // Arrivals within this long after the first sighting are summarized
+    window: Duration,
+    // Blocks are forgotten this long after the first sighting, later arrivals are ignored
+    retention: Duration,
+    // #{ block hash => sightings }
+    blocks: HashMap<Byte32, BlockSightings>,
+}
+
+struct BlockSightings {
+    number: u64,
+    first_seen: chrono::NaiveDateTime,
+    first_ip: String,
+    ips: HashSet<String>,
+    // In milliseconds, relative to the first sighting and to the header timestamp
+    delays: Vec<i64>,
+    header_delays: Vec<i64>,
+    summarized: bool,
+}
+
+/// Arrival of a block from a peer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sighting {
+    // In milliseconds since the first sighting of the block, 0 for the first one
+    pub delay: i64,
+    // In milliseconds since the header timestamp, negative if the clocks disagree
+    pub header_delay: i64,
+}
+
+/// Percentiles of the delays of a block, in milliseconds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropagationSummary {
+    pub block_hash: Byte32,
+    pub block_number: u64,
+    pub first_seen: chrono::NaiveDateTime,
+    pub first_ip: String,
+    pub n_peers: usize,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub header_p50: i64,
+    pub header_p90: i64,
+    pub header_p99: i64,
+}
+
+impl PropagationTracker {
+    pub fn new(window: Duration, retention: Duration) -> Self {
+        Self {
+            window,
+            retention,
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Record that `ip` relayed the block at `now`. Return None if `ip` relayed it before, the
+    /// block was already summarized, or its header timestamp is out of range.
+    pub fn record(
+        &mut self,
+        block_hash: Byte32,
+        block_number: u64,
+        // Header timestamp in milliseconds
+        timestamp: u64,
+        ip: &str,
+        now: chrono::NaiveDateTime,
+    ) -> Option<Sighting> {
+        // The header timestamp is chosen by the peer
+        let header_delay = i64::try_from(timestamp)
+            .ok()
+            .and_then(|timestamp| now.timestamp_millis().checked_sub(timestamp))?;
+        let block = self
+            .blocks
+            .entry(block_hash)
+            .or_insert_with(|| BlockSightings {
+                number: block_number,
+                first_seen: now,
+                first_ip: ip.to_string(),
+                ips: HashSet::new(),
+                delays: Vec::new(),
+                header_delays: Vec::new(),
+                summarized: false,
+            });
+        if block.summarized || !block.ips.insert(ip.to_string()) {
+            return None;
+        }
+        let sighting = Sighting {
+            delay: (now - block.first_seen).num_milliseconds(),
+            header_delay,
+        };
+        block.delays.push(sighting.delay);
+        block.header_delays.push(sighting.header_delay);
+        Some(sighting)
+    }
+
+    /// Return the summaries of the blocks whose window passed, and forget the expired blocks
+    pub fn drain(&mut self, now: chrono::NaiveDateTime) -> Vec<PropagationSummary> {
+        // Negative if the clock went backwards
+        let elapsed =
+            |block: &BlockSightings| (now - block.first_seen).to_std().unwrap_or_default();
+        let retention = self.retention;
+        self.blocks.retain(|_, block| elapsed(block) < retention);
+
+        let mut summaries = Vec::new();
+        for (block_hash, block) in self.blocks.iter_mut() {
+            if block.summarized || elapsed(block) < self.window {
+                continue;
+            }
+            block.summarized = true;
+            let mut delays = std::mem::take(&mut block.delays);
+            let mut header_delays = std::mem::take(&mut block.header_delays);
+            delays.sort_unstable();
+            header_delays.sort_unstable();
+            summaries.push(PropagationSummary {
+                block_hash: block_hash.clone(),
+                block_number: block.number,
+                first_seen: block.first_seen,
+                first_ip: block.first_ip.clone(),
+                n_peers: delays.len(),
+                p50: percentile(&delays, 50),
+                p90: percentile(&delays, 90),
+                p99: percentile(&delays, 99),
+                header_p50: percentile(&header_delays, 50),
+                header_p90: percentile(&header_delays, 90),
+                header_p99: percentile(&header_delays, 99),
+            });
+        }
+        summaries
+    }
+}
+
+#[test]
+fn test_propagation_tracker() {
+    let secs = Duration::from_secs;
+    let t0 = chrono::NaiveDateTime::from_timestamp_opt(1_700_000_000, 0).unwrap();
+    let at = |millis: i64| t0 + chrono::Duration::milliseconds(millis);
+    let block_hash = Byte32::new([1u8; 32]);
+    let timestamp = t0.timestamp_millis() as u64 - 500;
+    let mut tracker = PropagationTracker::new(secs(10), secs(60));
+
+    let first = tracker.record(block_hash.clone(), 1, timestamp, "1.1.1.1", at(0));
+    assert_eq!(
+        first,
+        Some(Sighting {
+            delay: 0,
+            header_delay: 500,
+        })
+    );
+    for (ip, delay) in [("2.2.2.2", 100), ("3.3.3.3", 300), ("4.4.4.4", 200)] {
+        assert!(tracker
+            .record(block_hash.clone(), 1, timestamp, ip, at(delay))
+            .is_some());
+    }
+    // Relayed again
+    assert_eq!(
+        tracker.record(block_hash.clone(), 1, timestamp, "2.2.2.2", at(400)),
+        None
+    );
+    // Header timestamp out of range
+    assert_eq!(
+        tracker.record(block_hash.clone(), 1, u64::MAX, "6.6.6.6", at(400)),
+        None
+    );
+
+    assert!(tracker.drain(at(9_999)).is_empty());
+    let summaries = tracker.drain(at(10_000));
+    assert_eq!(
+        summaries,
+        vec![PropagationSummary {
+            block_hash: block_hash.clone(),
+            block_number: 1,
+            first_seen: t0,
+            first_ip: "1.1.1.1".to_string(),
+            n_peers: 4,
+            p50: 100,
+            p90: 300,
+            p99: 300,
+            header_p50: 600,
+            header_p90: 800,
+            header_p99: 800,
+        }]
+    );
+    // Late arrivals are ignored until the block is forgotten
+    assert_eq!(
+        tracker.record(block_hash.clone(), 1, timestamp, "5.5.5.5", at(20_000)),
+        None
+    );
+    assert!(tracker.drain(at(20_000)).is_empty());
+}
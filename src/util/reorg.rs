@@ -0,0 +1,141 @@
+use crate::ckb_types::{core::BlockNumber, packed::Byte32};
+use std::collections::BTreeMap;
+
+/// Number of recent block hashes remembered to detect reorgs
+pub const REORG_DETECTION_WINDOW: usize = 1000;
+
+/// ReorgDetector remembers the hashes of the recently crawled blocks, to detect the blocks that
+/// no longer extend them.
+pub struct ReorgDetector {
+    capacity: usize,
+    // #{ number => hash }
+    hashes: BTreeMap<BlockNumber, Byte32>,
+}
+
+/// A detected reorganization, the blocks above `fork_number` were orphaned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reorg {
+    pub fork_number: BlockNumber,
+    pub old_tip_number: BlockNumber,
+    pub old_tip_hash: Byte32,
+    pub new_tip_hash: Byte32,
+}
+
+impl Reorg {
+    pub fn depth(&self) -> u64 {
+        self.old_tip_number - self.fork_number
+    }
+}
+
+impl ReorgDetector {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            hashes: Default::default(),
+        }
+    }
+
+    /// Remember the blocks crawled before a restart, in ascending order
+    pub fn seed(&mut self, hashes: Vec<(BlockNumber, Byte32)>) {
+        for (number, hash) in hashes {
+            self.record(number, hash);
+        }
+    }
+
+    /// Record a crawled block, forget the oldest one when exceeding the capacity
+    pub fn record(&mut self, number: BlockNumber, hash: Byte32) {
+        self.hashes.insert(number, hash);
+        while self.hashes.len() > self.capacity {
+            let oldest = *self.hashes.keys().next().expect("checked");
+            self.hashes.remove(&oldest);
+        }
+    }
+
+    /// Check whether the block `number` with `parent_hash` extends the remembered chain. If not,
+    /// walk back through the remembered blocks to the highest one still on the canonical chain,
+    /// according to `canonical_hash`, and forget the orphaned ones.
+    ///
+    /// When no remembered block is canonical, the reorg is deeper than the capacity; the oldest
+    /// remembered block minus one is reported as the fork point.
+    pub fn detect<F>(
+        &mut self,
+        number: BlockNumber,
+        parent_hash: &Byte32,
+        canonical_hash: F,
+    ) -> Option<Reorg>
+    where
+        F: Fn(BlockNumber) -> Byte32,
+    {
+        let parent_number = number.checked_sub(1)?;
+        let old_tip_hash = match self.hashes.get(&parent_number) {
+            Some(hash) if hash != parent_hash => hash.clone(),
+            _ => return None,
+        };
+
+        let mut fork_number = parent_number;
+        while let Some((&remembered_number, remembered_hash)) = self.hashes.iter().next_back() {
+            if &canonical_hash(remembered_number) == remembered_hash {
+                break;
+            }
+            fork_number = remembered_number.saturating_sub(1);
+            self.hashes.remove(&remembered_number);
+        }
+        Some(Reorg {
+            fork_number,
+            old_tip_number: parent_number,
+            old_tip_hash,
+            new_tip_hash: parent_hash.clone(),
+        })
+    }
+}
+
+#[test]
+fn test_detect_reorg() {
+    use crate::ckb_types::{h256, prelude::*};
+
+    let old_chain = |number: BlockNumber| -> Byte32 {
+        let mut hash = [0u8; 32];
+        hash[..8].copy_from_slice(&number.to_le_bytes());
+        hash.pack()
+    };
+    let new_chain = |number: BlockNumber| -> Byte32 {
+        if number > 7 {
+            let mut hash = [0xffu8; 32];
+            hash[..8].copy_from_slice(&number.to_le_bytes());
+            hash.pack()
+        } else {
+            old_chain(number)
+        }
+    };
+
+    let mut detector = ReorgDetector::new(5);
+    for number in 0..10 {
+        detector.record(number, old_chain(number));
+    }
+    // Extending the remembered chain, or unknown parent
+    assert_eq!(detector.detect(10, &old_chain(9), new_chain), None);
+    assert_eq!(detector.detect(12, &new_chain(11), new_chain), None);
+
+    // Blocks 8 and 9 were orphaned
+    let reorg = detector.detect(10, &new_chain(9), new_chain).unwrap();
+    assert_eq!(reorg.fork_number, 7);
+    assert_eq!(reorg.depth(), 2);
+    assert_eq!(reorg.old_tip_hash, old_chain(9));
+    assert_eq!(reorg.new_tip_hash, new_chain(9));
+    assert_eq!(detector.detect(8, &new_chain(7), new_chain), None);
+
+    // Seeded with the blocks crawled before a restart
+    let mut detector = ReorgDetector::new(5);
+    detector.seed((0..10).map(|number| (number, old_chain(number))).collect());
+    let reorg = detector.detect(10, &new_chain(9), new_chain).unwrap();
+    assert_eq!(reorg.fork_number, 7);
+
+    // Deeper than the capacity
+    let mut detector = ReorgDetector::new(2);
+    for number in 0..10 {
+        detector.record(number, old_chain(number));
+    }
+    let zero: Byte32 = h256!("0x0").pack();
+    let reorg = detector.detect(10, &zero, |_| zero.clone()).unwrap();
+    assert_eq!(reorg.fork_number, 7);
+}